@@ -0,0 +1,194 @@
+//! Embeds a symbol table for the hallway monitor, extracted from an already linked ELF.
+//!
+//! The table can only describe a binary that has already been linked, so it takes two builds and
+//! is opt-in. Build once, copy the ELF, e.g. `cp target/avr-atmega2560/debug/pasillo.elf
+//! symbols.elf`, and build again with `PASILLO_SYMBOLS_ELF=symbols.elf`. The table is sized to
+//! the symbols it holds, so embedding it moves everything after it: the second build's table
+//! describes the first build. When the table's size changed, a warning asks for another round; once
+//! it stops changing, the table is accurate. Any later change to the kernel makes it stale until the
+//! ELF is copied and the kernel rebuilt again.
+//!
+//! The copy is watched, so copying in a new ELF re-embeds it. The ELF being built isn't, as it is
+//! relinked by every build and would make every build dirty. Unset, the table is empty and takes no
+//! flash.
+//!
+//! With the `binary-log` feature, it also links in `log.x`, which keeps the interned log format
+//! strings out of the flashed image.
+
+use object::{Object, ObjectSymbol, SymbolKind};
+use std::{env, fs, path::PathBuf};
+
+/// Capacity of the table. Entries past this are dropped with a warning.
+const MAX_SYMBOLS: usize = 512;
+/// Capacity of the names. Must stay addressable by a `u16`.
+const NAMES_LEN: usize = 8 * 1024;
+/// Names of the table's own statics, to tell whether embedding changed its size.
+const TABLE_NAMES: [&str; 2] = ["debug::symbols::TABLE", "debug::symbols::NAMES"];
+/// Longer names keep their end, as it is the most specific part.
+const MAX_NAME_LEN: usize = 48;
+/// `addr: u24, space: u8, size: u16, name: u16, name_len: u8, _: u8`
+const ENTRY_LEN: usize = 10;
+
+/// Where the AVR toolchain maps the data space and EEPROM in ELF addresses.
+const ELF_DATA_OFFSET: u64 = 0x80_0000;
+const ELF_EEPROM_OFFSET: u64 = 0x81_0000;
+
+struct Symbol {
+    name: String,
+    /// Address space prefix, as used by the hallway monitor
+    space: u8,
+    addr: u32,
+    size: u32,
+}
+
+/// Read the defined function and object symbols, named without the crate, e.g. `main`.
+fn read_symbols(elf: &[u8]) -> Option<Vec<Symbol>> {
+    let elf = object::File::parse(elf).ok()?;
+    let symbols = elf
+        .symbols()
+        .filter(|symbol| matches!(symbol.kind(), SymbolKind::Text | SymbolKind::Data))
+        .filter(|symbol| symbol.is_definition())
+        .filter_map(|symbol| {
+            let name = format!("{:#}", rustc_demangle::demangle(symbol.name().ok()?));
+            let name = match name.strip_prefix(concat!(env!("CARGO_PKG_NAME"), "::")) {
+                Some(name) => name.to_string(),
+                None => name,
+            };
+            if name.is_empty() || name.starts_with('.') {
+                return None;
+            }
+            let value = symbol.address();
+            let (space, addr) = match value {
+                ELF_EEPROM_OFFSET.. => (b'e', value - ELF_EEPROM_OFFSET),
+                ELF_DATA_OFFSET.. => (b'r', value - ELF_DATA_OFFSET),
+                _ => (b'p', value),
+            };
+            Some(Symbol {
+                name,
+                space,
+                addr: addr as u32,
+                size: symbol.size() as u32,
+            })
+        })
+        .collect();
+    Some(symbols)
+}
+
+fn main() {
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=PASILLO_SYMBOLS_ELF");
+
+    if env::var_os("CARGO_FEATURE_BINARY_LOG").is_some() {
+        let script = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("log.x");
+        println!("cargo:rerun-if-changed={}", script.display());
+        println!("cargo:rustc-link-arg=-T{}", script.display());
+    }
+
+    let elf = env::var_os("PASILLO_SYMBOLS_ELF")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from);
+    // Only builds with the monitor use the table
+    let elf = elf.filter(|_| env::var_os("CARGO_FEATURE_MONITOR").is_some());
+    if let Some(elf) = &elf {
+        // `OUT_DIR` is `<profile>/build/<package>/out`
+        let profile = out_dir
+            .ancestors()
+            .nth(3)
+            .and_then(|dir| dir.canonicalize().ok());
+        let built = match (profile, elf.canonicalize()) {
+            (Some(profile), Ok(elf)) => elf.starts_with(profile),
+            _ => false,
+        };
+        if built {
+            println!(
+                "cargo:warning=PASILLO_SYMBOLS_ELF is this build's own output, copy it elsewhere \
+                 for its changes to be picked up"
+            );
+        } else {
+            println!("cargo:rerun-if-changed={}", elf.display());
+        }
+    }
+
+    let mut symbols = match &elf {
+        Some(path) => fs::read(path)
+            .ok()
+            .and_then(|elf| read_symbols(&elf))
+            .unwrap_or_else(|| {
+                println!("cargo:warning=no symbols read from {}", path.display());
+                Vec::new()
+            }),
+        None => Vec::new(),
+    };
+    // The sizes the table had in the ELF, to compare with the table embedded now
+    let embedded: Vec<u32> = TABLE_NAMES
+        .iter()
+        .map(|table| {
+            symbols
+                .iter()
+                .find(|symbol| symbol.name == *table)
+                .map_or(0, |symbol| symbol.size)
+        })
+        .collect();
+    symbols.sort_by(|a, b| (a.space, a.addr, &a.name).cmp(&(b.space, b.addr, &b.name)));
+    symbols.dedup_by(|a, b| (a.space, a.addr, &a.name) == (b.space, b.addr, &b.name));
+
+    let mut table = Vec::new();
+    let mut names = Vec::new();
+    let mut count = 0;
+    for symbol in &symbols {
+        let mut name = symbol.name.as_str();
+        if name.len() > MAX_NAME_LEN {
+            let mut start = name.len() - MAX_NAME_LEN;
+            while !name.is_char_boundary(start) {
+                start += 1;
+            }
+            name = &name[start..];
+        }
+        if count == MAX_SYMBOLS || names.len() + name.len() > NAMES_LEN {
+            println!(
+                "cargo:warning=symbol table full, {} of {} symbols embedded",
+                count,
+                symbols.len()
+            );
+            break;
+        }
+        let mut entry = [0; ENTRY_LEN];
+        entry[..3].copy_from_slice(&symbol.addr.to_le_bytes()[..3]);
+        entry[3] = symbol.space;
+        entry[4..6].copy_from_slice(&(symbol.size.min(u16::MAX as u32) as u16).to_le_bytes());
+        entry[6..8].copy_from_slice(&(names.len() as u16).to_le_bytes());
+        entry[8] = name.len() as u8;
+        table.extend_from_slice(&entry);
+        names.extend_from_slice(name.as_bytes());
+        count += 1;
+    }
+    if elf.is_some() && embedded != [table.len() as u32, names.len() as u32] {
+        println!(
+            "cargo:warning=symbol table resized, which moves what follows it; copy this build's \
+             ELF to PASILLO_SYMBOLS_ELF and build again"
+        );
+    }
+
+    let generated = format!(
+        "/// Number of symbols in [`TABLE`].\n\
+         const LEN: usize = {};\n\
+         /// [`ENTRY_LEN`] byte entries sorted by address.\n\
+         #[link_section = \".progmem.data\"]\n\
+         static TABLE: [u8; {}] = {:?};\n\
+         #[link_section = \".progmem.data\"]\n\
+         static NAMES: [u8; {}] = {:?};\n\
+         const ENTRY_LEN: usize = {};\n",
+        count,
+        table.len(),
+        table,
+        names.len(),
+        names,
+        ENTRY_LEN,
+    );
+    let path = out_dir.join("symbols.rs");
+    // Leave the file alone when unchanged, so that it isn't recompiled needlessly
+    if fs::read_to_string(&path).ok().as_deref() != Some(generated.as_str()) {
+        fs::write(&path, generated).unwrap();
+    }
+}
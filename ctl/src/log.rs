@@ -0,0 +1,429 @@
+//! Binary log records, as the kernel logs them with its `binary-log` feature; see
+//! `src/debug/log.rs` in the kernel.
+
+use crate::protocol::{crc16, decode};
+
+use anyhow::{Context, Result};
+use object::{Object, ObjectSection, ObjectSymbol};
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+/// Starts a record in the console's output.
+const FRAME_START: u8 = 0x1b;
+const SECTION: &str = ".pasillo_log";
+const LEVELS: [&str; 6] = ["OFF", "ERROR", "WARN", "INFO", "DEBUG", "TRACE"];
+/// Takes the level's place in the record that identifies the flashed image
+const IMAGE_RECORD: u8 = 0xff;
+
+/// The interned format strings, from the kernel's ELF.
+pub struct Formats {
+    section: Vec<u8>,
+    /// The text section's length and CRC-16, if the ELF has it, to check against the image's
+    image: Option<(u32, u16)>,
+}
+impl Formats {
+    /// Load the format strings, failing if the kernel wasn't built with binary logging.
+    pub fn load(path: &Path) -> Result<Self> {
+        let elf = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        let elf =
+            object::File::parse(&*elf).with_context(|| format!("parsing {}", path.display()))?;
+        let section = elf
+            .section_by_name(SECTION)
+            .with_context(|| format!("no {} section", SECTION))?;
+        Ok(Self {
+            section: section.data()?.to_vec(),
+            image: image(&elf),
+        })
+    }
+    /// A warning if the image record's `len: u32, crc: u16` don't match the ELF's.
+    fn check_image(&self, record: &[u8]) -> Option<String> {
+        let [a, b, c, d, low, high] = record[..] else {
+            return Some("<bad log record>".to_string());
+        };
+        let (len, crc) = (
+            u32::from_le_bytes([a, b, c, d]),
+            u16::from_le_bytes([low, high]),
+        );
+        match self.image {
+            Some(image) if image == (len, crc) => None,
+            Some((elf_len, elf_crc)) => Some(format!(
+                "warning: the ELF doesn't match the flashed image ({} bytes of text with CRC \
+                 0x{:04x}, not {} with 0x{:04x}), so lines will decode wrong",
+                elf_len, elf_crc, len, crc
+            )),
+            None => Some("warning: can't check the ELF against the flashed image".to_string()),
+        }
+    }
+    /// The module and format string interned at `address`.
+    fn get(&self, address: u16) -> Option<(&str, &str)> {
+        let text = self.section.get(address as usize..)?;
+        let text = std::str::from_utf8(&text[..text.iter().position(|&byte| byte == 0)?]).ok()?;
+        let (module, format) = text.split_once('\x1f')?;
+        Some((module.strip_prefix("pasillo::").unwrap_or(module), format))
+    }
+}
+
+/// The text section's length and CRC-16, as the kernel works them out from program memory: from
+/// 0 up to `_etext`.
+fn image(elf: &object::File) -> Option<(u32, u16)> {
+    let text = elf
+        .section_by_name(".text")
+        .filter(|text| text.address() == 0)?;
+    let end = elf
+        .symbols()
+        .find(|symbol| symbol.name() == Ok("_etext"))?
+        .address();
+    let bytes = text.data().ok()?.get(..end as usize)?;
+    Some((end as u32, crc16(bytes)))
+}
+
+#[derive(Debug, PartialEq)]
+enum Argument {
+    Unsigned(u32),
+    Signed(i32),
+    Bool(bool),
+    Char(char),
+    Str(String),
+}
+impl Argument {
+    /// Parse the argument at the start of `bytes`, and return the rest.
+    fn parse(bytes: &[u8]) -> Option<(Self, &[u8])> {
+        let (&tag, rest) = bytes.split_first()?;
+        let int = |len: usize| {
+            let mut value = [0; 4];
+            value[..len].copy_from_slice(rest.get(..len)?);
+            Some((u32::from_le_bytes(value), &rest[len..]))
+        };
+        // Sign extend a `len` byte integer
+        let signed = |len: usize| {
+            int(len).map(|(value, rest)| {
+                let shift = 32 - len as u32 * 8;
+                (Self::Signed((value << shift) as i32 >> shift), rest)
+            })
+        };
+        match tag {
+            0 => int(1).map(|(value, rest)| (Self::Unsigned(value), rest)),
+            1 => int(2).map(|(value, rest)| (Self::Unsigned(value), rest)),
+            2 => int(4).map(|(value, rest)| (Self::Unsigned(value), rest)),
+            3 => signed(1),
+            4 => signed(2),
+            5 => signed(4),
+            6 => int(1).map(|(value, rest)| (Self::Bool(value != 0), rest)),
+            7 => int(4).and_then(|(value, rest)| Some((Self::Char(char::from_u32(value)?), rest))),
+            8 => {
+                let (&len, rest) = rest.split_first()?;
+                let text = rest.get(..len as usize)?;
+                let text = String::from_utf8_lossy(text).into_owned();
+                Some((Self::Str(text), &rest[len as usize..]))
+            }
+            _ => None,
+        }
+    }
+    /// Format like ufmt would with `spec`, the part of a placeholder after `:`, e.g. `02x`.
+    fn render(&self, spec: &str) -> String {
+        let alternate = spec.starts_with('#');
+        let spec = spec.trim_start_matches('#');
+        let hex = spec.strip_suffix('x').or_else(|| spec.strip_suffix('X'));
+        let width = hex
+            .unwrap_or(spec)
+            .trim_start_matches('0')
+            .parse()
+            .unwrap_or(0);
+        let zero_pad = spec.starts_with('0');
+        let integer = match self {
+            Self::Unsigned(value) => Some(*value as i64),
+            Self::Signed(value) => Some(*value as i64),
+            _ => None,
+        };
+        match (integer, hex) {
+            (Some(value), Some(_)) => {
+                // Negative numbers as ufmt prints them, in two's complement
+                let mut text = format!("{:0width$x}", value as u32, width = width);
+                if spec.ends_with('X') {
+                    text = text.to_uppercase();
+                }
+                if alternate {
+                    text.insert_str(0, "0x");
+                }
+                text
+            }
+            (Some(value), None) if zero_pad => format!("{:0width$}", value, width = width),
+            _ => {
+                let text = match self {
+                    Self::Unsigned(value) => value.to_string(),
+                    Self::Signed(value) => value.to_string(),
+                    Self::Bool(value) => value.to_string(),
+                    Self::Char(value) => value.to_string(),
+                    Self::Str(value) => value.clone(),
+                };
+                format!("{:width$}", text, width = width)
+            }
+        }
+    }
+}
+
+/// Fill `format`'s placeholders with `arguments`, missing ones as `?`.
+fn render(format: &str, arguments: &[Argument]) -> String {
+    let mut text = String::new();
+    let mut arguments = arguments.iter();
+    let mut rest = format;
+    while let Some(idx) = rest.find(['{', '}']) {
+        text.push_str(&rest[..idx]);
+        let brace = &rest[idx..idx + 1];
+        rest = &rest[idx + 1..];
+        if let Some(after) = rest.strip_prefix(brace) {
+            text.push_str(brace);
+            rest = after;
+            continue;
+        }
+        if brace == "}" {
+            text.push('}');
+            continue;
+        }
+        let end = rest.find('}').unwrap_or(rest.len());
+        let spec = rest[..end].split_once(':').map_or("", |(_, spec)| spec);
+        match arguments.next() {
+            Some(argument) => text.push_str(&argument.render(spec)),
+            None => text.push('?'),
+        }
+        rest = rest.get(end + 1..).unwrap_or_default();
+    }
+    text.push_str(rest);
+    text
+}
+
+/// A decoded record as the kernel would've logged it as text, e.g. `[512] INFO reset: ...`.
+fn decode_record(record: &[u8], formats: &Formats) -> Option<String> {
+    let (&level, rest) = record.split_first()?;
+    let ticks = u32::from_le_bytes(rest.get(..4)?.try_into().ok()?);
+    let address = u16::from_le_bytes(rest.get(4..6)?.try_into().ok()?);
+    let (module, format) = formats.get(address)?;
+    let mut arguments = Vec::new();
+    let mut rest = &rest[6..];
+    // Arguments past the end of a full record were dropped, and the last may be cut off
+    while let Some((argument, after)) = Argument::parse(rest) {
+        arguments.push(argument);
+        rest = after;
+    }
+    let mut line = String::new();
+    let level = LEVELS.get(level as usize).unwrap_or(&"?");
+    write!(line, "[{}] {} {}: ", ticks, level, module).ok()?;
+    line.push_str(&render(format, &arguments));
+    Some(line)
+}
+
+/// Splits the console's output into text, passed through, and records, decoded into lines.
+#[derive(Default)]
+pub struct Decoder {
+    /// The record being received, if any
+    frame: Option<Vec<u8>>,
+}
+impl Decoder {
+    pub fn feed(
+        &mut self,
+        bytes: &[u8],
+        formats: &Formats,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        for &byte in bytes {
+            match (&mut self.frame, byte) {
+                (None, FRAME_START) => self.frame = Some(Vec::new()),
+                (None, _) => out.write_all(&[byte])?,
+                (Some(frame), 0) => {
+                    let line = match decode(frame) {
+                        // Nothing to show if the ELF matches
+                        Some(record) if record.first() == Some(&IMAGE_RECORD) => {
+                            formats.check_image(&record[1..])
+                        }
+                        record => Some(
+                            record
+                                .and_then(|record| decode_record(&record, formats))
+                                .unwrap_or_else(|| "<bad log record>".to_string()),
+                        ),
+                    };
+                    if let Some(line) = line {
+                        writeln!(out, "{}", line)?;
+                    }
+                    self.frame = None;
+                }
+                (Some(frame), _) => frame.push(byte),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::encode;
+
+    /// `reset: Reset by {} ({:#x})` at 0, then `task::tick: {:02x} {{{}}}`
+    const SECTION: &[u8] = b"pasillo::reset\x1fReset by {} ({:#x})\0task::tick\x1f{:02x} {{{}}}\0";
+    const TICK_FORMAT: u16 = 35;
+
+    fn formats(image: Option<(u32, u16)>) -> Formats {
+        Formats {
+            section: SECTION.to_vec(),
+            image,
+        }
+    }
+    fn record(level: u8, ticks: u32, format: u16, arguments: &[u8]) -> Vec<u8> {
+        let mut record = vec![level];
+        record.extend_from_slice(&ticks.to_le_bytes());
+        record.extend_from_slice(&format.to_le_bytes());
+        record.extend_from_slice(arguments);
+        record
+    }
+    fn feed(bytes: &[u8], formats: &Formats) -> String {
+        let mut out = Vec::new();
+        Decoder::default().feed(bytes, formats, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+    fn frame(record: &[u8]) -> Vec<u8> {
+        let mut frame = vec![FRAME_START];
+        frame.extend(encode(record));
+        frame
+    }
+
+    #[test]
+    fn formats_specs_like_ufmt() {
+        assert_eq!(Argument::Unsigned(5).render("02x"), "05");
+        assert_eq!(Argument::Unsigned(0x1ab).render("02x"), "1ab");
+        assert_eq!(Argument::Unsigned(255).render("#x"), "0xff");
+        assert_eq!(Argument::Unsigned(255).render("X"), "FF");
+        assert_eq!(Argument::Unsigned(7).render("04"), "0007");
+        assert_eq!(Argument::Signed(-7).render(""), "-7");
+        assert_eq!(Argument::Bool(true).render(""), "true");
+        assert_eq!(Argument::Char('x').render(""), "x");
+        assert_eq!(Argument::Str("hi".to_string()).render(""), "hi");
+    }
+
+    #[test]
+    fn formats_negative_hex_as_twos_complement() {
+        assert_eq!(Argument::Signed(-1).render("x"), "ffffffff");
+        assert_eq!(Argument::Signed(-2).render("#x"), "0xfffffffe");
+    }
+
+    #[test]
+    fn renders_placeholders_and_escapes() {
+        let arguments = [Argument::Unsigned(1), Argument::Unsigned(10)];
+        assert_eq!(render("{} and {:#x}", &arguments), "1 and 0xa");
+        assert_eq!(render("{{}} {}", &arguments), "{} 1");
+        assert_eq!(render("{{{}}}", &arguments), "{1}");
+        // Missing arguments
+        assert_eq!(render("{} {} {}", &arguments), "1 10 ?");
+        assert_eq!(render("no placeholders", &[]), "no placeholders");
+    }
+
+    #[test]
+    fn parses_each_argument_type() {
+        let rest: &[u8] = &[0xaa];
+        assert_eq!(
+            Argument::parse(&[0, 5, 0xaa]),
+            Some((Argument::Unsigned(5), rest))
+        );
+        assert_eq!(
+            Argument::parse(&[1, 0x34, 0x12]).unwrap().0,
+            Argument::Unsigned(0x1234)
+        );
+        assert_eq!(
+            Argument::parse(&[2, 1, 0, 0, 0x80]).unwrap().0,
+            Argument::Unsigned(0x8000_0001)
+        );
+        assert_eq!(Argument::parse(&[3, 0xff]).unwrap().0, Argument::Signed(-1));
+        assert_eq!(
+            Argument::parse(&[4, 0xfe, 0xff]).unwrap().0,
+            Argument::Signed(-2)
+        );
+        assert_eq!(
+            Argument::parse(&[5, 0xfd, 0xff, 0xff, 0xff]).unwrap().0,
+            Argument::Signed(-3)
+        );
+        assert_eq!(Argument::parse(&[6, 1]).unwrap().0, Argument::Bool(true));
+        assert_eq!(
+            Argument::parse(&[7, b'A', 0, 0, 0]).unwrap().0,
+            Argument::Char('A')
+        );
+        assert_eq!(
+            Argument::parse(&[8, 2, b'h', b'i']).unwrap().0,
+            Argument::Str("hi".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_cut_off_and_unknown_arguments() {
+        assert_eq!(Argument::parse(&[]), None);
+        assert_eq!(Argument::parse(&[2, 1, 2]), None);
+        assert_eq!(Argument::parse(&[8, 3, b'h']), None);
+        // Not a scalar value
+        assert_eq!(Argument::parse(&[7, 0, 0xd8, 0, 0]), None);
+        assert_eq!(Argument::parse(&[9, 0]), None);
+    }
+
+    #[test]
+    fn decodes_records() {
+        let formats = formats(None);
+        assert_eq!(
+            decode_record(&record(3, 512, 0, &[0, 5, 1, 0xab, 0]), &formats).unwrap(),
+            "[512] INFO reset: Reset by 5 (0xab)"
+        );
+        // The last argument cut off
+        assert_eq!(
+            decode_record(&record(1, 7, TICK_FORMAT, &[0, 0x0f, 1, 0x34]), &formats).unwrap(),
+            "[7] ERROR task::tick: 0f {?}"
+        );
+        // Not the start of a format string
+        assert_eq!(decode_record(&record(3, 0, 200, &[]), &formats), None);
+        assert_eq!(decode_record(&[3, 0, 0], &formats), None);
+    }
+
+    #[test]
+    fn splits_text_from_records() {
+        let formats = formats(None);
+        let mut bytes = b"text\n".to_vec();
+        bytes.extend(frame(&record(4, 1, 0, &[0, 0, 0, 0])));
+        bytes.extend(b"more\n");
+        assert_eq!(
+            feed(&bytes, &formats),
+            "text\n[1] DEBUG reset: Reset by 0 (0x0)\nmore\n"
+        );
+        assert_eq!(
+            feed(&[FRAME_START, 0x05, 0x01, 0x00], &formats),
+            "<bad log record>\n"
+        );
+    }
+
+    #[test]
+    fn keeps_records_split_across_reads() {
+        let formats = formats(None);
+        let bytes = frame(&record(2, 3, TICK_FORMAT, &[0, 0xa, 0, 1]));
+        let (first, second) = bytes.split_at(4);
+        let mut decoder = Decoder::default();
+        let mut out = Vec::new();
+        decoder.feed(first, &formats, &mut out).unwrap();
+        assert!(out.is_empty());
+        decoder.feed(second, &formats, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "[3] WARN task::tick: 0a {1}\n"
+        );
+    }
+
+    #[test]
+    fn checks_the_image_record() {
+        let image = [IMAGE_RECORD, 0x00, 0x10, 0, 0, 0x34, 0x12];
+        assert_eq!(feed(&frame(&image), &formats(Some((0x1000, 0x1234)))), "");
+        assert!(feed(&frame(&image), &formats(Some((0x1000, 0x4321))))
+            .starts_with("warning: the ELF doesn't match the flashed image"));
+        assert!(feed(&frame(&image), &formats(None)).starts_with("warning: can't check"));
+        assert_eq!(
+            feed(&frame(&image[..3]), &formats(None)),
+            "<bad log record>\n"
+        );
+    }
+}
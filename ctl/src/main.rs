@@ -0,0 +1,295 @@
+//! `pasillo-ctl`: drive a board's hallway monitor from the host, over a serial port or a pty.
+//!
+//! This is built for the host, while the kernel's cargo config targets the AVR, so give the host's
+//! target explicitly, e.g. from this directory:
+//! `cargo run --target x86_64-unknown-linux-gnu -- --port /dev/ttyACM0 markers`.
+
+mod log;
+mod protocol;
+mod record;
+mod symbols;
+
+use log::{Decoder, Formats};
+use protocol::{Address, Monitor};
+use record::CrashRecord;
+use symbols::Symbols;
+
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+/// Just past the internal SRAM; the data space below it is the registers and the SRAM.
+const DATA_END: u32 = 0x2200;
+/// Where the stack starts at reset.
+const RAMEND: u32 = 0x21ff;
+/// The kernel's task stacks.
+const STACK: &str = "task::stack::STACK";
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Serial port, or pty for a simulator
+    #[arg(short, long, env = "PASILLO_PORT")]
+    port: String,
+    #[arg(short, long, default_value_t = 57_600)]
+    baud_rate: u32,
+    /// The kernel's ELF, to name addresses
+    #[arg(short, long, default_value = concat!(env!("CARGO_MANIFEST_DIR"), "/../pasillo.elf"))]
+    elf: PathBuf,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Hex dump LEN bytes from ADDR, a marker or symbol NAME(+0xOFF) or (SPACE:)0xPOS
+    Read {
+        address: String,
+        #[arg(default_value = "0x40", value_parser = parse_hex)]
+        len: u32,
+    },
+    /// Write hex BYTES to ADDR, e.g. `write r:0x200 deadbeef`
+    Write { address: String, bytes: String },
+    /// Dump the data space up to the end of SRAM to FILE, so that offsets are addresses
+    DumpSram { file: PathBuf },
+    /// List the kernel's markers
+    Markers,
+    /// Dump every named register
+    Registers,
+    /// The last saved crash, then where the kernel panicked or stopped and the return addresses
+    /// on its stack
+    Crash {
+        /// Only decode the crash saved in an EEPROM dump, e.g. from `avrdude -U eeprom:r:FILE:r`
+        #[arg(long)]
+        eeprom: Option<PathBuf>,
+    },
+    /// Print the console's output as it comes, until interrupted. Binary log records are decoded
+    /// with the ELF's format strings, after checking at boot that it's the flashed one.
+    Log,
+}
+
+fn parse_hex(text: &str) -> Result<u32> {
+    u32::from_str_radix(text.trim_start_matches("0x"), 16).context("expected hex")
+}
+fn parse_bytes(text: &str) -> Result<Vec<u8>> {
+    let text = text.trim_start_matches("0x");
+    if !text.len().is_multiple_of(2) {
+        bail!("expected whole bytes");
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(&text[idx..idx + 2], 16).context("expected hex"))
+        .collect()
+}
+
+/// Parse an address like the monitor does, looking names up in the ELF and then the markers.
+fn parse_address(text: &str, symbols: &Symbols, monitor: &mut Monitor) -> Result<Address> {
+    if let Some(address) = Address::parse(text) {
+        return Ok(address);
+    }
+    let (name, offset) = match text.split_once('+') {
+        Some((name, offset)) => (name, parse_hex(offset)?),
+        None => (text, 0),
+    };
+    let address = match symbols.lookup(name) {
+        Some(symbol) => symbol.address,
+        None => monitor
+            .markers()?
+            .into_iter()
+            .find(|(marker, _)| marker == name)
+            .map(|(_, address)| address)
+            .with_context(|| format!("no marker or symbol `{}`", name))?,
+    };
+    Ok(address.offset(offset))
+}
+
+fn hex_dump(start: Address, bytes: &[u8], symbols: &Symbols) {
+    for (idx, row) in bytes.chunks(16).enumerate() {
+        let address = start.offset(idx as u32 * 16);
+        let hex = row
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let ascii = row
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect::<String>();
+        println!(
+            "{}:{:05x}  {:<47}  |{}|{}",
+            address.space,
+            address.addr,
+            hex,
+            ascii,
+            symbols.annotate(address)
+        );
+    }
+}
+
+fn print_record(bytes: &[u8], symbols: &Symbols) {
+    let Some(record) = CrashRecord::parse(bytes) else {
+        println!("No crash saved");
+        return;
+    };
+    println!("Crash #{}: {}", record.count, record.message);
+    println!(
+        "at {}:{}:{} (file ID {}) in task #{}, SP r:0x{:x}, SREG 0x{:02x}",
+        record.file,
+        record.line,
+        record.column,
+        record.file_id(),
+        record.task_id,
+        record.stack_pointer,
+        record.sreg
+    );
+    hex_dump(
+        Address::new('r', record.stack_pointer as u32 + 1),
+        &record.stack,
+        symbols,
+    );
+}
+
+/// Print the last saved crash, where the kernel panicked or stopped, and the likely return
+/// addresses on its stack: every 3 byte window, read as a big endian word address, that lands in
+/// a function.
+fn crash(monitor: &mut Monitor, symbols: &Symbols) -> Result<()> {
+    print_record(&monitor.read(record::EEPROM_ADDRESS, record::LEN)?, symbols);
+    let Some(stack_pointer) = monitor.stack_pointer()? else {
+        println!("The kernel hasn't panicked or stopped");
+        return Ok(());
+    };
+    let stack_pointer = Address::new('r', stack_pointer as u32);
+    // A task stack, or the stack at the end of SRAM
+    let end = symbols
+        .lookup(STACK)
+        .filter(|stack| {
+            (stack.address.addr..=stack.address.addr + stack.size).contains(&stack_pointer.addr)
+        })
+        .map(|stack| stack.address.addr + stack.size)
+        .unwrap_or(RAMEND);
+    println!("Stack {}..=r:0x{:x}", stack_pointer, end);
+
+    let start = stack_pointer.offset(1);
+    let stack = monitor.read(start, end.saturating_sub(stack_pointer.addr) as usize)?;
+    hex_dump(start, &stack, symbols);
+    let mut frame = 0;
+    let mut idx = 0;
+    while idx + 3 <= stack.len() {
+        let ret = Address::new(
+            'p',
+            u32::from_be_bytes([0, stack[idx], stack[idx + 1], stack[idx + 2]]) * 2,
+        );
+        match symbols.resolve(ret) {
+            Some((symbol, offset)) if symbol.size > 0 && offset > 0 => {
+                println!(
+                    "#{} {}: {}{}",
+                    frame,
+                    start.offset(idx as u32),
+                    ret,
+                    symbols.annotate(ret)
+                );
+                frame += 1;
+                idx += 3;
+            }
+            _ => idx += 1,
+        }
+    }
+    Ok(())
+}
+
+fn log(cli: &Cli) -> Result<()> {
+    // Without them, e.g. if the kernel logs text, the console is passed through as is
+    let formats = match Formats::load(&cli.elf) {
+        Ok(formats) => Some(formats),
+        Err(error) => {
+            eprintln!("warning: not decoding log records, {:#}", error);
+            None
+        }
+    };
+    let mut decoder = Decoder::default();
+    let mut port = serialport::new(&cli.port, cli.baud_rate)
+        .timeout(Duration::from_secs(1))
+        .open()
+        .with_context(|| format!("opening {}", cli.port))?;
+    let mut buffer = [0; 256];
+    loop {
+        match port.read(&mut buffer) {
+            Ok(len) => {
+                match &formats {
+                    Some(formats) => decoder.feed(&buffer[..len], formats, &mut io::stdout())?,
+                    None => io::stdout().write_all(&buffer[..len])?,
+                }
+                io::stdout().flush()?;
+            }
+            Err(error) if error.kind() == io::ErrorKind::TimedOut => {}
+            Err(error) => return Err(error.into()),
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    if let Command::Log = cli.command {
+        return log(&cli);
+    }
+
+    let symbols = Symbols::load(&cli.elf).unwrap_or_else(|error| {
+        eprintln!("warning: no symbols, {:#}", error);
+        Symbols::default()
+    });
+    if let Command::Crash {
+        eeprom: Some(eeprom),
+    } = &cli.command
+    {
+        let bytes = fs::read(eeprom).with_context(|| format!("reading {}", eeprom.display()))?;
+        let start = record::EEPROM_ADDRESS.addr as usize;
+        print_record(bytes.get(start..).unwrap_or_default(), &symbols);
+        return Ok(());
+    }
+    let mut monitor = Monitor::connect(&cli.port, cli.baud_rate)?;
+    match &cli.command {
+        Command::Read { address, len } => {
+            let start = parse_address(address, &symbols, &mut monitor)?;
+            let bytes = monitor.read(start, *len as usize)?;
+            hex_dump(start, &bytes, &symbols);
+        }
+        Command::Write { address, bytes } => {
+            let start = parse_address(address, &symbols, &mut monitor)?;
+            monitor.write(start, &parse_bytes(bytes)?)?;
+        }
+        Command::DumpSram { file } => {
+            let bytes = monitor.read(Address::new('r', 0), DATA_END as usize)?;
+            fs::write(file, bytes).with_context(|| format!("writing {}", file.display()))?;
+        }
+        Command::Markers => {
+            for (idx, (name, address)) in monitor.markers()?.iter().enumerate() {
+                println!(
+                    "{} --> {} @{}{}",
+                    idx,
+                    name,
+                    address,
+                    symbols.annotate(*address)
+                );
+            }
+        }
+        Command::Registers => {
+            for (name, address, value) in monitor.registers()? {
+                println!("{} @{} = 0x{:x}", name, address, value);
+            }
+        }
+        Command::Crash { .. } => crash(&mut monitor, &symbols)?,
+        Command::Log => unreachable!(),
+    }
+    monitor.exit()
+}
@@ -0,0 +1,389 @@
+//! The host side of the hallway monitor's binary mode; see `src/debug/protocol.rs` in the kernel.
+
+use anyhow::{bail, Context, Result};
+use serialport::SerialPort;
+use std::{io::Read, thread, time::Duration};
+
+/// Switches the monitor to binary mode.
+pub const MAGIC: &str = "\x1bpasillo-bin";
+pub const VERSION: u8 = 1;
+/// Most bytes read or written by one request.
+pub const MAX_DATA_LEN: usize = 64;
+const ERROR: u8 = 0x80;
+const NACK: u8 = 0xff;
+
+pub const HELLO: u8 = 0x00;
+pub const READ: u8 = 0x01;
+pub const WRITE: u8 = 0x02;
+pub const MARKER: u8 = 0x04;
+pub const REGISTER: u8 = 0x05;
+pub const EXIT: u8 = 0x06;
+pub const STACK_POINTER: u8 = 0x07;
+
+/// `PErrorVariant` names by code.
+const ERRORS: [&str; 12] = [
+    "Unknown",
+    "Stdio",
+    "OutOfMemory",
+    "WouldBlock",
+    "Timeout",
+    "InvalidDescriptor",
+    "Busy",
+    "Bus",
+    "Checksum",
+    "Overflow",
+    "Permission",
+    "NotFound",
+];
+/// `PErrorVariant::NotFound`'s code, e.g. past the last marker or register.
+const NOT_FOUND: u8 = 11;
+const RETRIES: usize = 3;
+
+/// An error the monitor responded with, by its `PErrorVariant` code.
+#[derive(Debug, PartialEq, Eq)]
+pub struct MonitorError(pub u8);
+impl MonitorError {
+    pub fn is_not_found(&self) -> bool {
+        self.0 == NOT_FOUND
+    }
+}
+impl std::fmt::Display for MonitorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(ERRORS.get(self.0 as usize).unwrap_or(&"Unknown"))
+    }
+}
+impl std::error::Error for MonitorError {}
+
+/// Whether `error` is the monitor responding with `NotFound`.
+fn is_not_found(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<MonitorError>()
+        .is_some_and(MonitorError::is_not_found)
+}
+
+/// CRC-16/CCITT-FALSE: polynomial 0x1021, initially 0xffff.
+pub fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0xffff, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// COBS encode `frame`, including the trailing 0. Frames are shorter than 254 bytes.
+pub fn encode(frame: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(frame.len() + 2);
+    for block in frame.split(|&byte| byte == 0) {
+        encoded.push(block.len() as u8 + 1);
+        encoded.extend_from_slice(block);
+    }
+    encoded.push(0);
+    encoded
+}
+/// COBS decode `encoded`, without its trailing 0.
+pub fn decode(encoded: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len());
+    let mut rest = encoded;
+    while let Some(&code) = rest.first() {
+        let block = rest.get(1..code as usize)?;
+        decoded.extend_from_slice(block);
+        rest = &rest[code as usize..];
+        if code != 0xff && !rest.is_empty() {
+            decoded.push(0);
+        }
+    }
+    Some(decoded)
+}
+
+pub struct Monitor {
+    port: Box<dyn SerialPort>,
+    seq: u8,
+    /// Whether the monitor has acknowledged an `EXIT`, so it's back in text mode.
+    exited: bool,
+}
+impl Monitor {
+    /// Open the monitor at `path` (a serial port or a pty), and switch it to binary mode. The
+    /// monitor must be waiting for input, e.g. after a panic.
+    pub fn connect(path: &str, baud_rate: u32) -> Result<Self> {
+        let port = serialport::new(path, baud_rate)
+            .timeout(Duration::from_secs(1))
+            .open()
+            .with_context(|| format!("opening {}", path))?;
+        let mut monitor = Self {
+            port,
+            seq: 0,
+            exited: false,
+        };
+        // The first newline gets past the panic handler's prompt, if it is still waiting
+        monitor
+            .port
+            .write_all(format!("\n{}\n", MAGIC).as_bytes())?;
+        thread::sleep(Duration::from_millis(200));
+        monitor.port.clear(serialport::ClearBuffer::Input)?;
+        let version = monitor.request(HELLO, &[])?;
+        if version != [VERSION] {
+            bail!("unsupported protocol version {:?}", version);
+        }
+        Ok(monitor)
+    }
+
+    /// Read one frame, skipping any text before it.
+    fn receive(&mut self) -> Result<Vec<u8>> {
+        let mut encoded = Vec::new();
+        let mut byte = [0];
+        loop {
+            self.port
+                .read_exact(&mut byte)
+                .context("waiting for the monitor")?;
+            if byte[0] == 0 {
+                return Ok(encoded);
+            }
+            encoded.push(byte[0]);
+        }
+    }
+
+    /// Send a request, returning the response's payload.
+    pub fn request(&mut self, opcode: u8, payload: &[u8]) -> Result<Vec<u8>> {
+        self.seq = self.seq.wrapping_add(1);
+        let mut frame = vec![opcode, self.seq];
+        frame.extend_from_slice(payload);
+        frame.extend_from_slice(&crc16(&frame).to_le_bytes());
+        let encoded = encode(&frame);
+
+        for _ in 0..RETRIES {
+            self.port.write_all(&encoded)?;
+            loop {
+                let Some(response) = decode(&self.receive()?) else {
+                    continue;
+                };
+                let Some((body, crc)) = response.split_last_chunk::<2>() else {
+                    continue;
+                };
+                if crc16(body) != u16::from_le_bytes(*crc) || body.len() < 2 {
+                    continue;
+                }
+                match (body[0], body[1]) {
+                    // Corrupted on the way there
+                    (NACK, _) => break,
+                    (_, seq) if seq != self.seq => continue,
+                    (response, _) if response == opcode => return Ok(body[2..].to_vec()),
+                    (response, _) if response == opcode | ERROR => {
+                        let code = body.get(2).copied().unwrap_or_default();
+                        return Err(MonitorError(code).into());
+                    }
+                    _ => continue,
+                }
+            }
+        }
+        bail!("the monitor kept rejecting the request")
+    }
+
+    /// Read `len` bytes from `address`, in as many requests as needed.
+    pub fn read(&mut self, address: Address, len: usize) -> Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(len);
+        while bytes.len() < len {
+            let chunk = (len - bytes.len()).min(MAX_DATA_LEN);
+            let mut payload = address.offset(bytes.len() as u32).encode().to_vec();
+            payload.push(chunk as u8);
+            bytes.extend(self.request(READ, &payload)?);
+        }
+        Ok(bytes)
+    }
+    pub fn write(&mut self, address: Address, bytes: &[u8]) -> Result<()> {
+        for (idx, chunk) in bytes.chunks(MAX_DATA_LEN).enumerate() {
+            let mut payload = address
+                .offset((idx * MAX_DATA_LEN) as u32)
+                .encode()
+                .to_vec();
+            payload.extend_from_slice(chunk);
+            self.request(WRITE, &payload)?;
+        }
+        Ok(())
+    }
+    /// All markers, as `(name, address)`.
+    pub fn markers(&mut self) -> Result<Vec<(String, Address)>> {
+        let mut markers = Vec::new();
+        loop {
+            let response = match self.request(MARKER, &[markers.len() as u8]) {
+                Ok(response) => response,
+                // No markers at all
+                Err(error) if markers.is_empty() && is_not_found(&error) => return Ok(markers),
+                Err(error) => return Err(error),
+            };
+            let (&count, rest) = response.split_first().context("short response")?;
+            let address = Address::decode(rest).context("short response")?;
+            markers.push((String::from_utf8_lossy(&rest[5..]).into_owned(), address));
+            if markers.len() >= count as usize {
+                return Ok(markers);
+            }
+        }
+    }
+    /// All named registers, as `(name, address, value)`.
+    pub fn registers(&mut self) -> Result<Vec<(String, Address, u16)>> {
+        let mut registers = Vec::new();
+        loop {
+            let response = match self.request(REGISTER, &(registers.len() as u16).to_le_bytes()) {
+                Ok(response) => response,
+                // Past the last one
+                Err(error) if is_not_found(&error) => return Ok(registers),
+                Err(error) => return Err(error),
+            };
+            let address = Address::decode(&response).context("short response")?;
+            let value = response.get(6..8).context("short response")?;
+            registers.push((
+                String::from_utf8_lossy(&response[8..]).into_owned(),
+                address,
+                u16::from_le_bytes([value[0], value[1]]),
+            ));
+        }
+    }
+    /// Where the kernel panicked or stopped, if it did.
+    pub fn stack_pointer(&mut self) -> Result<Option<u16>> {
+        let response = self.request(STACK_POINTER, &[])?;
+        Ok(match response[..] {
+            [1, low, high] => Some(u16::from_le_bytes([low, high])),
+            _ => None,
+        })
+    }
+    /// Hand the monitor back to humans, failing if it doesn't acknowledge that.
+    pub fn exit(mut self) -> Result<()> {
+        self.exited = true;
+        self.request(EXIT, &[])
+            .context("leaving binary mode")
+            .map(|_| ())
+    }
+}
+impl Drop for Monitor {
+    /// Hand the monitor back to humans, e.g. after an error, if [`Monitor::exit`] wasn't called.
+    fn drop(&mut self) {
+        if !self.exited {
+            let _ = self.request(EXIT, &[]);
+        }
+    }
+}
+
+/// An address as the monitor writes it, e.g. `r:0x200`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Address {
+    /// `r` (data), `p` (program), `e` (EEPROM) or `i` (I/O)
+    pub space: char,
+    pub addr: u32,
+}
+impl Address {
+    pub const fn new(space: char, addr: u32) -> Self {
+        Self { space, addr }
+    }
+    pub fn offset(self, by: u32) -> Self {
+        Self::new(self.space, self.addr.wrapping_add(by))
+    }
+    fn encode(self) -> [u8; 5] {
+        let [a, b, c, d] = self.addr.to_le_bytes();
+        [self.space as u8, a, b, c, d]
+    }
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let bytes: [u8; 5] = bytes.get(..5)?.try_into().ok()?;
+        Some(Self::new(
+            bytes[0] as char,
+            u32::from_le_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]),
+        ))
+    }
+    /// Parse `(<space>:)<0xpos>`, where the space defaults to data.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (space, pos) = match text.split_once(':') {
+            Some((space, pos)) if matches!(space, "r" | "p" | "e" | "i") => {
+                (space.chars().next()?, pos)
+            }
+            Some(_) => return None,
+            None => ('r', text),
+        };
+        let addr = u32::from_str_radix(pos.trim_start_matches("0x"), 16).ok()?;
+        Some(Self::new(space, addr))
+    }
+}
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:0x{:x}", self.space, self.addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc16_known_vector() {
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+        assert_eq!(crc16(&[]), 0xffff);
+    }
+
+    #[test]
+    fn cobs_known_vectors() {
+        assert_eq!(encode(&[]), [0x01, 0x00]);
+        assert_eq!(encode(&[0x00]), [0x01, 0x01, 0x00]);
+        assert_eq!(encode(&[0x00, 0x00]), [0x01, 0x01, 0x01, 0x00]);
+        assert_eq!(
+            encode(&[0x11, 0x22, 0x00, 0x33]),
+            [0x03, 0x11, 0x22, 0x02, 0x33, 0x00]
+        );
+        assert_eq!(
+            decode(&[0x03, 0x11, 0x22, 0x02, 0x33]).unwrap(),
+            [0x11, 0x22, 0x00, 0x33]
+        );
+    }
+
+    #[test]
+    fn cobs_round_trips() {
+        let frames: [&[u8]; 5] = [
+            &[],
+            &[0x00],
+            &[0x01, 0x00, 0x00, 0x02],
+            &[0x06, 0x01, 0x12, 0x34],
+            &[0xff; 200],
+        ];
+        for frame in frames {
+            let encoded = encode(frame);
+            let (&end, body) = encoded.split_last().unwrap();
+            assert_eq!(end, 0);
+            assert!(!body.contains(&0));
+            assert_eq!(decode(body).unwrap(), frame);
+        }
+    }
+
+    #[test]
+    fn cobs_rejects_truncated() {
+        assert_eq!(decode(&[0x05, 0x11]), None);
+    }
+
+    #[test]
+    fn errors_match_the_kernel() {
+        assert_eq!(MonitorError(0).to_string(), "Unknown");
+        assert_eq!(MonitorError(8).to_string(), "Checksum");
+        assert_eq!(MonitorError(9).to_string(), "Overflow");
+        assert_eq!(MonitorError(NOT_FOUND).to_string(), "NotFound");
+        assert!(MonitorError(NOT_FOUND).is_not_found());
+        assert!(!MonitorError(4).is_not_found());
+        // Codes the host doesn't know yet
+        assert_eq!(MonitorError(200).to_string(), "Unknown");
+    }
+
+    #[test]
+    fn only_not_found_is_not_found() {
+        assert!(is_not_found(&MonitorError(NOT_FOUND).into()));
+        assert!(!is_not_found(&MonitorError(4).into()));
+        assert!(!is_not_found(&anyhow::anyhow!("waiting for the monitor")));
+    }
+
+    #[test]
+    fn addresses_round_trip() {
+        let address = Address::parse("p:0x1234").unwrap();
+        assert!(address == Address::new('p', 0x1234));
+        assert!(Address::decode(&address.encode()) == Some(address));
+        assert_eq!(address.to_string(), "p:0x1234");
+        assert!(Address::parse("200") == Some(Address::new('r', 0x200)));
+        assert!(Address::parse("x:0x10").is_none());
+    }
+}
@@ -0,0 +1,71 @@
+//! The crash record the kernel saves when it panics; see `src/crash.rs` in the kernel.
+
+use crate::protocol::{crc16, Address};
+
+/// Where the record is kept in EEPROM.
+pub const EEPROM_ADDRESS: Address = Address::new('e', 0xf80);
+pub const LEN: usize = 113;
+const MAGIC: &[u8] = b"crsh";
+const CRC_OFFSET: usize = 110;
+
+pub struct CrashRecord {
+    pub count: u16,
+    pub file: String,
+    pub line: u16,
+    pub column: u16,
+    pub task_id: u8,
+    pub sreg: u8,
+    pub stack_pointer: u16,
+    pub message: String,
+    pub stack: Vec<u8>,
+}
+impl CrashRecord {
+    /// Parse a record, `None` if there isn't a valid one.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let bytes = bytes.get(..LEN)?;
+        let crc = u16::from_le_bytes([bytes[CRC_OFFSET], bytes[CRC_OFFSET + 1]]);
+        if !bytes.starts_with(MAGIC) || crc16(&bytes[..CRC_OFFSET]) != crc {
+            return None;
+        }
+        let u16_at = |idx: usize| u16::from_le_bytes([bytes[idx], bytes[idx + 1]]);
+        let text = |field: &[u8]| {
+            let len = field
+                .iter()
+                .position(|&byte| byte == 0)
+                .unwrap_or(field.len());
+            String::from_utf8_lossy(&field[..len]).into_owned()
+        };
+        Some(Self {
+            count: u16_at(4),
+            line: u16_at(6),
+            column: u16_at(8),
+            task_id: bytes[10],
+            sreg: bytes[11],
+            stack_pointer: u16_at(12),
+            file: text(&bytes[14..38]),
+            message: text(&bytes[38..78]),
+            stack: bytes[78..110].to_vec(),
+        })
+    }
+    /// The ID the panic site's file blinks as; see `file_id` in the kernel's `src/panic.rs`.
+    pub fn file_id(&self) -> u8 {
+        file_id(&self.file)
+    }
+}
+
+fn file_id(file: &str) -> u8 {
+    let name = file.rsplit(['/', '\\']).next().unwrap_or(file);
+    (crc16(name.as_bytes()) % 100) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_id_ignores_directories() {
+        assert_eq!(file_id("src/debug/hallway.rs"), file_id("hallway.rs"));
+        assert_eq!(file_id("ug/hallway.rs"), file_id("hallway.rs"));
+        assert_eq!(file_id("hallway.rs"), (crc16(b"hallway.rs") % 100) as u8);
+    }
+}
@@ -0,0 +1,94 @@
+//! Symbols from the kernel's ELF, named like the hallway monitor names them.
+
+use crate::protocol::Address;
+
+use anyhow::{Context, Result};
+use object::{Object, ObjectSymbol, SymbolKind};
+use std::{fs, path::Path};
+
+/// Where the AVR toolchain maps the data space and EEPROM in ELF addresses.
+const ELF_DATA_OFFSET: u64 = 0x80_0000;
+const ELF_EEPROM_OFFSET: u64 = 0x81_0000;
+
+pub struct Symbol {
+    /// Path without the crate name, e.g. `task::stack::STACK`
+    pub name: String,
+    pub address: Address,
+    pub size: u32,
+}
+impl Symbol {
+    /// Whether `name` is this symbol's name, or the last components of its path.
+    fn matches(&self, name: &str) -> bool {
+        self.name == name
+            || self
+                .name
+                .strip_suffix(name)
+                .is_some_and(|path| path.ends_with("::"))
+    }
+    fn contains(&self, address: Address) -> bool {
+        address.space == self.address.space
+            && address.addr >= self.address.addr
+            && address.addr - self.address.addr < self.size.max(1)
+    }
+}
+
+#[derive(Default)]
+pub struct Symbols {
+    symbols: Vec<Symbol>,
+}
+impl Symbols {
+    pub fn load(path: &Path) -> Result<Self> {
+        let elf = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        let elf =
+            object::File::parse(&*elf).with_context(|| format!("parsing {}", path.display()))?;
+        let mut symbols = elf
+            .symbols()
+            .filter(|symbol| matches!(symbol.kind(), SymbolKind::Text | SymbolKind::Data))
+            .filter(|symbol| symbol.is_definition())
+            .filter_map(|symbol| {
+                let name = format!("{:#}", rustc_demangle::demangle(symbol.name().ok()?));
+                let name = name
+                    .strip_prefix("pasillo::")
+                    .map(str::to_string)
+                    .unwrap_or(name);
+                let value = symbol.address();
+                let address = match value {
+                    ELF_EEPROM_OFFSET.. => Address::new('e', (value - ELF_EEPROM_OFFSET) as u32),
+                    ELF_DATA_OFFSET.. => Address::new('r', (value - ELF_DATA_OFFSET) as u32),
+                    _ => Address::new('p', value as u32),
+                };
+                Some(Symbol {
+                    name,
+                    address,
+                    size: symbol.size() as u32,
+                })
+            })
+            .collect::<Vec<_>>();
+        symbols.sort_by_key(|symbol| (symbol.address.space, symbol.address.addr));
+        Ok(Self { symbols })
+    }
+
+    /// Find a symbol by name, preferring a full match.
+    pub fn lookup(&self, name: &str) -> Option<&Symbol> {
+        self.symbols
+            .iter()
+            .find(|symbol| symbol.name == name)
+            .or_else(|| self.symbols.iter().find(|symbol| symbol.matches(name)))
+    }
+    /// The innermost symbol containing `address`, and the offset into it.
+    pub fn resolve(&self, address: Address) -> Option<(&Symbol, u32)> {
+        self.symbols
+            .iter()
+            .rev()
+            .find(|symbol| symbol.contains(address))
+            .map(|symbol| (symbol, address.addr - symbol.address.addr))
+    }
+    /// ` <name+0xoffset>`, or nothing if `address` doesn't resolve.
+    pub fn annotate(&self, address: Address) -> String {
+        match self.resolve(address) {
+            Some((symbol, 0)) => format!(" <{}>", symbol.name),
+            Some((symbol, offset)) => format!(" <{}+0x{:x}>", symbol.name, offset),
+            None => String::new(),
+        }
+    }
+}
@@ -0,0 +1,12 @@
+/* Interned log format strings, for the `binary-log` feature; see `src/debug/log.rs`.
+ *
+ * Added to the toolchain's default script. The section is an INFO section at 0, so the ELF keeps
+ * it for the host to decode with, but it isn't loaded, and a string's address is its offset. */
+SECTIONS
+{
+  .pasillo_log 0 (INFO) :
+  {
+    KEEP(*(.pasillo_log .pasillo_log.*))
+  }
+}
+INSERT AFTER .comment;
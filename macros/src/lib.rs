@@ -0,0 +1,237 @@
+//! Procedural macros for Pasillo.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use syn::{parse::Parser, punctuated::Punctuated, Expr, Lit, LitStr, Token};
+
+/// Bytes of string literals moved into program memory by this compiler invocation.
+static PROGMEM_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// A piece of a format string.
+#[derive(Debug, PartialEq, Eq)]
+enum Piece {
+    Literal(String),
+    /// The contents of a `{...}` placeholder, including the braces.
+    Placeholder(String),
+}
+
+/// Split a `ufmt` format string into literal text and placeholders, unescaping `{{` and `}}`.
+fn split_format(format: &str) -> Result<Vec<Piece>, &'static str> {
+    let mut pieces = Vec::new();
+    let mut literal = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(char) = chars.next() {
+        match char {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                if !literal.is_empty() {
+                    pieces.push(Piece::Literal(core::mem::take(&mut literal)));
+                }
+                let mut placeholder = String::from('{');
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(char) => placeholder.push(char),
+                        None => return Err("unterminated `{` in format string"),
+                    }
+                }
+                placeholder.push('}');
+                pieces.push(Piece::Placeholder(placeholder));
+            }
+            '}' => return Err("unmatched `}` in format string"),
+            char => literal.push(char),
+        }
+    }
+    if !literal.is_empty() {
+        pieces.push(Piece::Literal(literal));
+    }
+    Ok(pieces)
+}
+
+fn expand_pm_uwrite(input: TokenStream2) -> syn::Result<TokenStream2> {
+    let args = Punctuated::<Expr, Token![,]>::parse_terminated.parse2(input.clone())?;
+    let mut args = args.into_iter();
+    let writer = args
+        .next()
+        .ok_or_else(|| syn::Error::new_spanned(&input, "expected a writer"))?;
+    let format = match args.next() {
+        Some(Expr::Lit(syn::ExprLit {
+            lit: Lit::Str(format),
+            ..
+        })) => format,
+        Some(Expr::Group(group)) => match *group.expr {
+            Expr::Lit(syn::ExprLit {
+                lit: Lit::Str(format),
+                ..
+            }) => format,
+            other => return Err(syn::Error::new_spanned(other, "expected a string literal")),
+        },
+        Some(other) => return Err(syn::Error::new_spanned(other, "expected a string literal")),
+        None => return Err(syn::Error::new_spanned(&input, "expected a format string")),
+    };
+    let pieces =
+        split_format(&format.value()).map_err(|err| syn::Error::new(format.span(), err))?;
+
+    let mut args = args.collect::<Vec<_>>().into_iter();
+    let mut writes = Vec::new();
+    for piece in pieces {
+        let write = match piece {
+            Piece::Literal(literal) => {
+                PROGMEM_BYTES.fetch_add(literal.len(), Ordering::Relaxed);
+                let literal = LitStr::new(&literal, format.span());
+                quote! { ufmt::uwrite!(&mut *__pm_writer, "{}", crate::types::string::pm_str!(#literal)) }
+            }
+            Piece::Placeholder(placeholder) => {
+                let arg = args.next().ok_or_else(|| {
+                    syn::Error::new(format.span(), "more placeholders than arguments")
+                })?;
+                let placeholder = LitStr::new(&placeholder, format.span());
+                quote! { ufmt::uwrite!(&mut *__pm_writer, #placeholder, #arg) }
+            }
+        };
+        writes.push(write);
+    }
+    if let Some(extra) = args.next() {
+        return Err(syn::Error::new_spanned(extra, "argument never used"));
+    }
+    if writes.is_empty() {
+        writes.push(quote! { ufmt::uwrite!(&mut *__pm_writer, "") });
+    }
+
+    // Overwritten by each invocation, so that the file ends up with the crate's total rather than
+    // a line per invocation in the build output
+    if let Some(path) = std::env::var_os("PASILLO_PROGMEM_REPORT") {
+        let total = PROGMEM_BYTES.load(Ordering::Relaxed);
+        let _ = std::fs::write(
+            path,
+            format!(
+                "{} bytes of format strings moved to program memory\n",
+                total
+            ),
+        );
+    }
+
+    let last = writes.pop().unwrap();
+    if writes.is_empty() {
+        return Ok(quote! {
+            {
+                let __pm_writer = &mut *#writer;
+                #last
+            }
+        });
+    }
+    Ok(quote! {
+        {
+            let __pm_writer = &mut *#writer;
+            'pm_uwrite: {
+                #(
+                    if let Err(err) = #writes {
+                        break 'pm_uwrite Err(err);
+                    }
+                )*
+                #last
+            }
+        }
+    })
+}
+
+/// Like [`ufmt::uwrite!`], but the literal pieces of the format string are placed in program
+/// memory rather than SRAM. Each placeholder is formatted with its own `ufmt::uwrite!`.
+///
+/// Must be invoked from within the kernel crate, as it expands to `crate::types::string::pm_str!`.
+/// Set `PASILLO_PROGMEM_REPORT` to a file while building to have how many bytes were moved written
+/// to it, e.g. `PASILLO_PROGMEM_REPORT=progmem.txt cargo build` from a clean build.
+#[proc_macro]
+pub fn pm_uwrite(input: TokenStream) -> TokenStream {
+    expand_pm_uwrite(input.into())
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn literal(text: &str) -> Piece {
+        Piece::Literal(text.to_string())
+    }
+    fn placeholder(text: &str) -> Piece {
+        Piece::Placeholder(text.to_string())
+    }
+
+    #[test]
+    fn splits_literals_and_placeholders() {
+        assert_eq!(
+            split_format("a {} b {:?}").unwrap(),
+            [
+                literal("a "),
+                placeholder("{}"),
+                literal(" b "),
+                placeholder("{:?}")
+            ]
+        );
+        assert_eq!(split_format("").unwrap(), []);
+        assert_eq!(
+            split_format("{}{}").unwrap(),
+            [placeholder("{}"), placeholder("{}")]
+        );
+    }
+
+    #[test]
+    fn keeps_format_specs() {
+        assert_eq!(
+            split_format("0x{:02x} {:#x} {:04}").unwrap(),
+            [
+                literal("0x"),
+                placeholder("{:02x}"),
+                literal(" "),
+                placeholder("{:#x}"),
+                literal(" "),
+                placeholder("{:04}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn unescapes_braces() {
+        assert_eq!(split_format("{{}}").unwrap(), [literal("{}")]);
+        assert_eq!(
+            split_format("{{{}}}").unwrap(),
+            [literal("{"), placeholder("{}"), literal("}")]
+        );
+    }
+
+    #[test]
+    fn rejects_unbalanced_braces() {
+        assert!(split_format("{").is_err());
+        assert!(split_format("a {:x").is_err());
+        assert!(split_format("}").is_err());
+    }
+
+    #[test]
+    fn expands_each_piece() {
+        let expanded = expand_pm_uwrite(quote! { w, "x={:02x}!", 5 })
+            .unwrap()
+            .to_string();
+        assert!(expanded.contains("pm_str ! (\"x=\")"));
+        assert!(expanded.contains("\"{:02x}\" , 5"));
+        assert!(expanded.contains("pm_str ! (\"!\")"));
+    }
+
+    #[test]
+    fn checks_arguments() {
+        assert!(expand_pm_uwrite(quote! { w, "{} {}", 1 }).is_err());
+        assert!(expand_pm_uwrite(quote! { w, "{}", 1, 2 }).is_err());
+        assert!(expand_pm_uwrite(quote! { w, 1 }).is_err());
+        assert!(expand_pm_uwrite(quote! { w }).is_err());
+    }
+}
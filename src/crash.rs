@@ -0,0 +1,219 @@
+//! Crash records, so that a panic can be looked into after the fact.
+//!
+//! The panic handler saves a [`CrashRecord`] to [`PREVIOUS`] in `.noinit` RAM, which survives a
+//! warm reset (e.g. the reset button or the watchdog), and then to the end of EEPROM at
+//! [`EEPROM_CRASH`], which survives losing power. On boot, [`report_previous`] prints the newest
+//! valid copy once. It stays in [`PREVIOUS`] until the next panic, to be downloaded with
+//! `pasillo-ctl crash`, or read out of EEPROM with e.g. `avrdude -U eeprom:r:eeprom.bin:r`.
+
+use crate::{
+    debug::{
+        access::{read_eeprom, write_eeprom, Address, AddressSpace},
+        console::{debug_println, helper_print},
+    },
+    panic,
+    shared::EEPROM_CRASH,
+    task::{scheduler::TASKS, state::TaskState},
+    types::magic::Magic,
+    utils::{crc16, BoundedWriter},
+};
+
+use core::{
+    mem::{offset_of, size_of},
+    panic::PanicInfo,
+    ptr::addr_of_mut,
+};
+
+pub const MESSAGE_LEN: usize = 40;
+/// Only the end of the file path is kept, as the start is the same for every file.
+pub const FILE_LEN: usize = 24;
+pub const STACK_SNIPPET_LEN: usize = 32;
+/// The task ID recorded when no task was running.
+pub const NO_TASK: u8 = 0xff;
+const SREG: Address = Address::new(AddressSpace::Io, 0x3f);
+
+/// Everything is little endian, and strings are NUL padded.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CrashRecord {
+    _magic: [u8; 4],
+    /// Crashes so far, including this one
+    pub count: u16,
+    pub line: u16,
+    pub column: u16,
+    pub task_id: u8,
+    pub sreg: u8,
+    pub stack_pointer: u16,
+    pub file: [u8; FILE_LEN],
+    pub message: [u8; MESSAGE_LEN],
+    /// The stack just above `stack_pointer`, where the innermost return addresses are
+    pub stack: [u8; STACK_SNIPPET_LEN],
+    /// CRC-16/CCITT-FALSE of everything before it
+    crc: u16,
+    /// Whether it was reported on boot. Not covered by `crc`, so that it can be written alone.
+    reported: u8,
+}
+unsafe impl Magic<4> for CrashRecord {
+    const MAGIC: [u8; 4] = [b'c', b'r', b's', b'h'];
+}
+// Must fit in the EEPROM reserved for it
+const _: () = assert!(CrashRecord::LEN <= 0x1000 - EEPROM_CRASH as usize);
+impl CrashRecord {
+    pub const LEN: usize = size_of::<Self>();
+    const EMPTY: Self = Self {
+        _magic: [0; 4],
+        count: 0,
+        line: 0,
+        column: 0,
+        task_id: NO_TASK,
+        sreg: 0,
+        stack_pointer: 0,
+        file: [0; FILE_LEN],
+        message: [0; MESSAGE_LEN],
+        stack: [0; STACK_SNIPPET_LEN],
+        crc: 0,
+        reported: 0,
+    };
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self as *const Self as *const u8, Self::LEN) }
+    }
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self as *mut Self as *mut u8, Self::LEN) }
+    }
+    fn checksum(&self) -> u16 {
+        crc16(&self.bytes()[..offset_of!(Self, crc)])
+    }
+    pub fn is_valid(&self) -> bool {
+        Self::is_magic(self) && self.crc == self.checksum()
+    }
+    pub fn file(&self) -> &str {
+        text(&self.file)
+    }
+    pub fn message(&self) -> &str {
+        text(&self.message)
+    }
+}
+
+/// The last crash. Garbage after a cold boot, until [`load`] reads it from EEPROM.
+#[link_section = ".noinit"]
+pub static mut PREVIOUS: CrashRecord = CrashRecord::EMPTY;
+
+/// The valid UTF-8 before the padding; truncation may have split a character.
+fn text(bytes: &[u8]) -> &str {
+    let len = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    match core::str::from_utf8(&bytes[..len]) {
+        Ok(text) => text,
+        Err(error) => core::str::from_utf8(&bytes[..error.valid_up_to()]).unwrap_or_default(),
+    }
+}
+/// Copy as much of the end of `text` into `field` as fits.
+fn copy_tail(field: &mut [u8], text: &str) {
+    let len = text.len().min(field.len());
+    field[..len].copy_from_slice(&text.as_bytes()[text.len() - len..]);
+}
+
+fn read_from_eeprom(record: &mut CrashRecord) {
+    for (idx, byte) in record.bytes_mut().iter_mut().enumerate() {
+        *byte = read_eeprom(EEPROM_CRASH + idx as u16);
+    }
+}
+/// Only bytes that differ are written, so rewriting the same record is cheap.
+fn write_to_eeprom(record: &CrashRecord) {
+    for (idx, byte) in record.bytes().iter().enumerate() {
+        write_eeprom(EEPROM_CRASH + idx as u16, *byte);
+    }
+}
+
+/// Make [`PREVIOUS`] the newest valid crash record, if there is one. The copy in RAM is only valid
+/// after a warm reset, and is newer than the one in EEPROM if power was lost while saving it.
+pub fn load() -> Option<&'static CrashRecord> {
+    let previous = unsafe { &mut *addr_of_mut!(PREVIOUS) };
+    if previous.is_valid() {
+        write_to_eeprom(previous);
+    } else {
+        read_from_eeprom(previous);
+    }
+    previous.is_valid().then_some(previous)
+}
+
+/// Save a record of a panic. `stack_pointer` and `sreg` should be from when the panic started.
+pub fn save(info: &PanicInfo, stack_pointer: u16, sreg: u8) {
+    let count = load()
+        .map_or(0, |previous| previous.count)
+        .saturating_add(1);
+    let record = unsafe { &mut *addr_of_mut!(PREVIOUS) };
+    *record = CrashRecord::EMPTY;
+    record._magic = CrashRecord::MAGIC;
+    record.count = count;
+    if let Some(location) = info.location() {
+        copy_tail(&mut record.file, location.file());
+        record.line = location.line().min(u16::MAX as u32) as u16;
+        record.column = location.column().min(u16::MAX as u32) as u16;
+    }
+    let mut len = 0;
+    let message = &mut record.message;
+    panic::write_message(
+        info,
+        &mut BoundedWriter::new(MESSAGE_LEN, |byte| {
+            if let Some(slot) = message.get_mut(len) {
+                *slot = byte;
+                len += 1;
+            }
+        }),
+    );
+    record.task_id = unsafe { TASKS.iter() }
+        .find(|task| task.state == TaskState::Running)
+        .map_or(NO_TASK, |task| task.id);
+    record.sreg = sreg;
+    record.stack_pointer = stack_pointer;
+    for (idx, byte) in record.stack.iter_mut().enumerate() {
+        let address = Address::new(AddressSpace::Data, stack_pointer as u32 + 1 + idx as u32);
+        *byte = address.read().unwrap_or_default();
+    }
+    record.crc = record.checksum();
+    // Already in RAM. EEPROM is last, as it takes ~3.4ms a byte.
+    write_to_eeprom(record);
+}
+
+/// SREG as it is now, for [`save`].
+pub fn sreg() -> u8 {
+    SREG.read().unwrap_or_default()
+}
+
+pub fn print(record: &CrashRecord) {
+    debug_println!("CRASH #{}: {}", record.count, record.message());
+    debug_println!(
+        "at {}:{}:{} (file ID {}) in task #{}, SP 0x{:04x}, SREG 0x{:02x}",
+        record.file(),
+        record.line,
+        record.column,
+        panic::file_id(record.file()),
+        record.task_id,
+        record.stack_pointer,
+        record.sreg
+    );
+    helper_print!("", "", "stack:");
+    record
+        .stack
+        .iter()
+        .for_each(|byte| helper_print!("", "", " {:02x}", *byte));
+    helper_print!("", '\n', "");
+}
+
+/// Print the last crash, if it hasn't been already.
+pub fn report_previous() {
+    let Some(previous) = load() else {
+        return;
+    };
+    if previous.reported != 0 {
+        return;
+    }
+    debug_println!("The kernel crashed before this boot");
+    print(previous);
+    unsafe { PREVIOUS.reported = 1 };
+    write_eeprom(EEPROM_CRASH + offset_of!(CrashRecord, reported) as u16, 1);
+}
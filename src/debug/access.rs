@@ -0,0 +1,244 @@
+//! Byte access to every address space of the `atmega2560`: data (SRAM and registers), program
+//! memory (flash), EEPROM, and the I/O space.
+
+use crate::{
+    types::{
+        error::{PError, PErrorVariant, PStaticResult},
+        string::pm_str,
+    },
+    utils::write_hex,
+};
+
+use avr_device::interrupt;
+use core::arch::asm;
+
+/// Size of the program memory in bytes. Anything past 64 KiB needs `ELPM`.
+pub const FLASH_SIZE: u32 = 256 * 1024;
+pub const EEPROM_SIZE: u32 = 4 * 1024;
+/// Size of the data space, including external memory.
+pub const DATA_SIZE: u32 = 64 * 1024;
+/// The I/O space, including the extended I/O registers, which are mapped into the data space at
+/// [`IO_DATA_OFFSET`].
+pub const IO_SIZE: u32 = 0x1e0;
+pub const IO_DATA_OFFSET: u32 = 0x20;
+
+// EEPROM registers, in the I/O space
+const EECR: u8 = 0x1f;
+const EEDR: u8 = 0x20;
+const EEARL: u8 = 0x21;
+const EEARH: u8 = 0x22;
+const EECR_EERE: u8 = 1 << 0;
+const EECR_EEPE: u8 = 1 << 1;
+/// `SPMCSR`, whose `SPMEN` bit must be clear before writing to the EEPROM.
+const SPMCSR: u8 = 0x37;
+const SPMCSR_SPMEN: u8 = 1 << 0;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum AddressSpace {
+    /// SRAM, the register file and the memory mapped registers
+    Data,
+    /// Flash
+    Program,
+    Eeprom,
+    /// The I/O space as used by `in`/`out`, i.e. data address minus [`IO_DATA_OFFSET`]
+    Io,
+}
+impl AddressSpace {
+    /// The prefix used by the hallway monitor, i.e. `p` in `p:0x1234`.
+    pub const fn prefix(self) -> char {
+        match self {
+            Self::Data => 'r',
+            Self::Program => 'p',
+            Self::Eeprom => 'e',
+            Self::Io => 'i',
+        }
+    }
+    pub fn from_prefix(prefix: char) -> Option<Self> {
+        match prefix {
+            'r' => Some(Self::Data),
+            'p' => Some(Self::Program),
+            'e' => Some(Self::Eeprom),
+            'i' => Some(Self::Io),
+            _ => None,
+        }
+    }
+    pub const fn size(self) -> u32 {
+        match self {
+            Self::Data => DATA_SIZE,
+            Self::Program => FLASH_SIZE,
+            Self::Eeprom => EEPROM_SIZE,
+            Self::Io => IO_SIZE,
+        }
+    }
+}
+
+/// A byte address within an [`AddressSpace`]. Program memory addresses are byte addresses, not the
+/// word addresses used by function pointers.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Address {
+    pub space: AddressSpace,
+    pub addr: u32,
+}
+impl Address {
+    pub const fn new(space: AddressSpace, addr: u32) -> Self {
+        Self { space, addr }
+    }
+    pub fn data(ptr: *const u8) -> Self {
+        Self::new(AddressSpace::Data, ptr.addr() as u32)
+    }
+    /// The address of a function, from its (word addressed) pointer.
+    pub fn function(ptr: *const u8) -> Self {
+        Self::new(AddressSpace::Program, ptr.addr() as u32 * 2)
+    }
+    pub fn offset(self, by: i32) -> Self {
+        Self::new(self.space, self.addr.wrapping_add_signed(by))
+    }
+    pub fn read(self) -> PStaticResult<u8> {
+        read(self)
+    }
+    /// # Safety
+    /// See [`write`].
+    #[require_unsafe_in_body]
+    pub unsafe fn write(self, byte: u8) -> PStaticResult<()> {
+        unsafe { write(self, byte) }
+    }
+}
+impl ufmt::uDisplay for Address {
+    /// `<prefix>:0x<addr>`
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized,
+    {
+        f.write_char(self.space.prefix())?;
+        f.write_str(":0x")?;
+        write_hex(f, self.addr, 1)
+    }
+}
+
+fn check_bounds(address: Address) -> PStaticResult<()> {
+    if address.addr >= address.space.size() {
+        return Err(PError::new(
+            PErrorVariant::Overflow,
+            pm_str!("address out of range"),
+        ));
+    }
+    Ok(())
+}
+
+/// Read a byte from any address space.
+pub fn read(address: Address) -> PStaticResult<u8> {
+    check_bounds(address)?;
+    Ok(match address.space {
+        AddressSpace::Data => unsafe { read_data(address.addr as u16) },
+        AddressSpace::Io => unsafe { read_data((address.addr + IO_DATA_OFFSET) as u16) },
+        AddressSpace::Program => read_program(address.addr),
+        AddressSpace::Eeprom => read_eeprom(address.addr as u16),
+    })
+}
+
+/// Write a byte to any address space. Program memory is refused.
+///
+/// # Safety
+/// Writing to arbitrary data or I/O addresses can corrupt anything, including this function's own
+/// stack frame.
+#[require_unsafe_in_body]
+pub unsafe fn write(address: Address, byte: u8) -> PStaticResult<()> {
+    check_bounds(address)?;
+    match address.space {
+        AddressSpace::Data => unsafe { write_data(address.addr as u16, byte) },
+        AddressSpace::Io => unsafe { write_data((address.addr + IO_DATA_OFFSET) as u16, byte) },
+        AddressSpace::Program => {
+            return Err(PError::new(
+                PErrorVariant::Permission,
+                pm_str!("flash is read-only"),
+            ))
+        }
+        AddressSpace::Eeprom => write_eeprom(address.addr as u16, byte),
+    }
+    Ok(())
+}
+
+/// # Safety
+/// Reads `addr` of the data space, which may have side effects for registers.
+#[require_unsafe_in_body]
+unsafe fn read_data(addr: u16) -> u8 {
+    unsafe { core::ptr::read_volatile(addr as usize as *const u8) }
+}
+#[require_unsafe_in_body]
+unsafe fn write_data(addr: u16, byte: u8) {
+    unsafe { core::ptr::write_volatile(addr as usize as *mut u8, byte) }
+}
+fn read_io(addr: u8) -> u8 {
+    unsafe { read_data(addr as u16 + IO_DATA_OFFSET as u16) }
+}
+fn write_io(addr: u8, byte: u8) {
+    unsafe { write_data(addr as u16 + IO_DATA_OFFSET as u16, byte) }
+}
+
+/// Read a byte of program memory with `ELPM`, so that all of the flash can be reached.
+pub fn read_program(addr: u32) -> u8 {
+    interrupt::free(|_| {
+        let byte: u8;
+        unsafe {
+            // `RAMPZ` (0x3b) is reset to 0 afterwards, as it is assumed to be
+            asm!(
+                "out 0x3b, {rampz}",
+                "elpm {byte}, Z",
+                "out 0x3b, r1",
+                rampz = in(reg) (addr >> 16) as u8,
+                byte = out(reg) byte,
+                in("Z") addr as u16,
+            );
+        }
+        byte
+    })
+}
+
+/// The end of the text section as a program memory byte address, from the linker's `_etext`.
+pub fn text_end() -> u32 {
+    let (low, high, extended): (u8, u8, u8);
+    unsafe {
+        asm!(
+            "ldi {low}, lo8(_etext)",
+            "ldi {high}, hi8(_etext)",
+            "ldi {extended}, hh8(_etext)",
+            low = out(reg_upper) low,
+            high = out(reg_upper) high,
+            extended = out(reg_upper) extended,
+            options(pure, nomem, nostack, preserves_flags),
+        );
+    }
+    u32::from_le_bytes([low, high, extended, 0])
+}
+
+fn wait_eeprom() {
+    while read_io(EECR) & EECR_EEPE != 0 || read_io(SPMCSR) & SPMCSR_SPMEN != 0 {}
+}
+pub fn read_eeprom(addr: u16) -> u8 {
+    interrupt::free(|_| {
+        wait_eeprom();
+        write_io(EEARH, (addr >> 8) as u8);
+        write_io(EEARL, addr as u8);
+        write_io(EECR, EECR_EERE);
+        read_io(EEDR)
+    })
+}
+/// Erase and write a byte of EEPROM. Blocks for the ~3.4ms the previous write may still take.
+pub fn write_eeprom(addr: u16, byte: u8) {
+    if read_eeprom(addr) == byte {
+        // Spare the cell an erase cycle
+        return;
+    }
+    interrupt::free(|_| {
+        wait_eeprom();
+        write_io(EEARH, (addr >> 8) as u8);
+        write_io(EEARL, addr as u8);
+        write_io(EEDR, byte);
+        // `EEPM` is undefined at reset, so clear it for an atomic erase and write
+        write_io(EECR, 0);
+        // `EEPE` must be set within four cycles of `EEMPE`
+        unsafe {
+            asm!("sbi 0x1f, 2", "sbi 0x1f, 1");
+        }
+    })
+}
@@ -0,0 +1,134 @@
+//! Stack backtraces, by scanning a stack for return addresses.
+//!
+//! Nothing records frame sizes, so every 3 byte window of the stack is a candidate: a call on the
+//! `atmega2560` pushes the 22 bit return address (in words) most significant byte last, so it
+//! reads big endian upwards from the stack pointer. Candidates outside the text section are
+//! skipped, and the rest are flagged by whether a call instruction precedes them.
+//!
+//! Where the kernel panicked or stopped is also kept for GDB: the stack pointer, and r0 to r31
+//! as saved by [`save_registers`].
+
+use crate::{
+    debug::{
+        access::{text_end, Address, AddressSpace},
+        disasm,
+    },
+    task::stack::{STACK, STACK_LEN},
+};
+
+pub use crate::task::stack::stack_pointer;
+
+use core::arch::{asm, global_asm};
+
+/// Last address of the internal SRAM, where the stack starts at reset.
+pub const RAMEND: u16 = 0x21ff;
+/// The interrupt vectors precede the code, 57 `jmp`s of 4 bytes each.
+const VECTORS_LEN: u32 = 57 * 4;
+
+/// The stack pointer when the kernel panicked, or stopped at a breakpoint.
+static mut PANIC_STACK_POINTER: Option<u16> = None;
+/// r0 to r31 when the kernel panicked, or stopped at a breakpoint.
+static mut REGISTERS: [u8; 32] = [0; 32];
+
+// Neither routine changes SREG, or any register but the ones restored. Restoring is only done
+// from assembly, e.g. `pasillo_stop_here`, as Rust code would undo it on return.
+global_asm!(
+    ".global pasillo_save_registers",
+    "pasillo_save_registers:",
+    ".irp n,0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+    "sts {registers}+\\n, r\\n",
+    ".endr",
+    "ret",
+    ".global pasillo_restore_registers",
+    "pasillo_restore_registers:",
+    ".irp n,0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+    "lds r\\n, {registers}+\\n",
+    ".endr",
+    "ret",
+    registers = sym REGISTERS,
+);
+
+/// Save r0 to r31 as they are, for [`registers`].
+#[inline(always)]
+pub fn save_registers() {
+    unsafe { asm!("call pasillo_save_registers") };
+}
+/// r0 to r31 as saved by [`save_registers`].
+pub fn registers() -> [u8; 32] {
+    unsafe { REGISTERS }
+}
+/// Change the saved registers, to take effect when they're restored on continuing from a
+/// breakpoint.
+pub fn set_registers(registers: [u8; 32]) {
+    unsafe { REGISTERS = registers };
+}
+
+/// Remember the current stack pointer as where the kernel panicked or stopped; see
+/// [`panic_stack_pointer`].
+#[inline(always)]
+pub fn capture() {
+    unsafe { PANIC_STACK_POINTER = Some(stack_pointer()) };
+}
+/// Forget the captured stack pointer, once the kernel continues from a breakpoint.
+pub fn release() {
+    unsafe { PANIC_STACK_POINTER = None };
+}
+pub fn panic_stack_pointer() -> Option<u16> {
+    unsafe { PANIC_STACK_POINTER }
+}
+
+/// The highest address of the stack `stack_pointer` is in: one of the task stacks, or else the
+/// stack at the end of SRAM.
+pub fn stack_end(stack_pointer: u16) -> u16 {
+    unsafe { STACK.iter() }
+        .map(|stack| stack.as_ptr().addr() as u16)
+        .find(|&start| (start..=start + STACK_LEN as u16).contains(&stack_pointer))
+        // `jump_to_stack` points to just past the end of a task stack
+        .map(|start| start + STACK_LEN as u16)
+        .unwrap_or(RAMEND)
+}
+
+/// A return address found on the stack.
+pub struct Frame {
+    /// Where on the stack the return address is
+    pub at: Address,
+    pub ret: Address,
+    /// Whether a call instruction precedes `ret`. If not, it is likely stale data that only looks
+    /// like a return address.
+    pub after_call: bool,
+}
+
+/// Call `f` with the candidate return addresses between `stack_pointer` and `end`, innermost
+/// first. Stops early if `f` returns `true`.
+pub fn walk<F>(stack_pointer: u16, end: u16, mut f: F)
+where
+    F: FnMut(Frame) -> bool,
+{
+    let text = VECTORS_LEN..text_end();
+    let byte = |addr: u16| {
+        Address::new(AddressSpace::Data, addr as u32)
+            .read()
+            .unwrap_or_default()
+    };
+    // The stack pointer points to the next free byte
+    let mut at = stack_pointer.saturating_add(1);
+    while at.saturating_add(2) <= end {
+        let word = u32::from_be_bytes([0, byte(at), byte(at + 1), byte(at + 2)]);
+        let ret = word * 2;
+        if !text.contains(&ret) {
+            at += 1;
+            continue;
+        }
+        let after_call = disasm::follows_call(ret);
+        let frame = Frame {
+            at: Address::new(AddressSpace::Data, at as u32),
+            ret: Address::new(AddressSpace::Program, ret),
+            after_call,
+        };
+        if f(frame) {
+            return;
+        }
+        // A confirmed return address can't overlap with the next one
+        at += if after_call { 3 } else { 1 };
+    }
+}
@@ -0,0 +1,182 @@
+//! Cooperative breakpoints for the hallway monitor.
+//!
+//! Flash can't be patched cheaply, so code opts in with [`breakpoint!`] sites instead. Each site
+//! has an ID, and stops in the monitor while its ID is enabled. Sending Ctrl-C over the console, or
+//! stepping from the monitor, stops at whichever site is reached next. A watchpoint changing stops
+//! in the kernel tick instead, see [`stop_for_watchpoint`].
+//!
+//! Ctrl-C is noticed by the USART's receive interrupt, so sites only check a flag. Anything else
+//! received is kept for the console's next read.
+
+#[cfg(feature = "monitor")]
+use crate::debug::{
+    backtrace,
+    console::{self, debug_println},
+    gdb,
+    hallway::HallwayMonitor,
+    watchpoint,
+};
+
+#[cfg(feature = "monitor")]
+use avr_device::interrupt;
+#[cfg(feature = "monitor")]
+use core::arch::global_asm;
+#[cfg(feature = "monitor")]
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Number of breakpoint IDs, as sites are identified by a `u8`.
+pub const NUM_BREAKPOINTS: usize = 256;
+/// ASCII ETX, as sent by Ctrl-C
+pub const BREAK_IN: u8 = 0x03;
+
+/// One bit per breakpoint ID, set if enabled.
+#[cfg(feature = "monitor")]
+static mut ENABLED: [u8; NUM_BREAKPOINTS / 8] = [0; NUM_BREAKPOINTS / 8];
+/// Stop at the next site, enabled or not.
+#[cfg(feature = "monitor")]
+static mut STEP: bool = false;
+/// Whether the kernel is stopped somewhere it can continue from, rather than panicked.
+#[cfg(feature = "monitor")]
+static mut STOPPED: bool = false;
+/// The site the kernel is stopped at, if any.
+#[cfg(feature = "monitor")]
+static mut STOPPED_AT: Option<u8> = None;
+/// Whether Ctrl-C was sent over the console since the last stop.
+#[cfg(feature = "monitor")]
+static BREAK_IN_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(feature = "monitor")]
+pub fn is_enabled(id: u8) -> bool {
+    let bits = unsafe { ENABLED[id as usize / 8] };
+    bits & (1 << (id % 8)) != 0
+}
+#[cfg(feature = "monitor")]
+pub fn set_enabled(id: u8, enabled: bool) {
+    let mask = 1 << (id % 8);
+    unsafe {
+        if enabled {
+            ENABLED[id as usize / 8] |= mask;
+        } else {
+            ENABLED[id as usize / 8] &= !mask;
+        }
+    }
+}
+/// All enabled breakpoint IDs, in order.
+#[cfg(feature = "monitor")]
+pub fn enabled() -> impl Iterator<Item = u8> {
+    (0..=u8::MAX).filter(|&id| is_enabled(id))
+}
+
+/// Stop at the next site reached once the monitor is left, e.g. to step from site to site.
+#[cfg(feature = "monitor")]
+pub fn step() {
+    unsafe { STEP = true };
+}
+#[cfg(feature = "monitor")]
+pub fn is_stopped() -> bool {
+    unsafe { STOPPED }
+}
+#[cfg(feature = "monitor")]
+pub fn stopped_at() -> Option<u8> {
+    unsafe { STOPPED_AT }
+}
+
+/// Flag Ctrl-C for the next site, and keep anything else for the console's next read.
+#[cfg(feature = "monitor")]
+#[avr_device::interrupt(atmega2560)]
+fn USART0_RX() {
+    // Reading it clears the interrupt, even if the console isn't set
+    let byte = unsafe { &*arduino_hal::pac::USART0::ptr() }
+        .udr0
+        .read()
+        .bits();
+    if byte == BREAK_IN {
+        BREAK_IN_REQUESTED.store(true, Ordering::Relaxed);
+    } else {
+        interrupt::free(|cs| console::buffer_input(cs, byte));
+    }
+}
+
+/// Whether the site `id` should stop; see [`breakpoint!`].
+#[cfg(feature = "monitor")]
+#[inline(always)]
+pub fn should_break(id: u8) -> bool {
+    is_enabled(id) || unsafe { STEP } || BREAK_IN_REQUESTED.load(Ordering::Relaxed)
+}
+
+// Saves r0 to r31 for GDB, and restores them after, as GDB may have changed them
+#[cfg(feature = "monitor")]
+global_asm!(
+    ".global pasillo_stop_here",
+    "pasillo_stop_here:",
+    "call pasillo_save_registers",
+    "call {stopped}",
+    "call pasillo_restore_registers",
+    "ret",
+    stopped = sym stopped,
+);
+#[cfg(feature = "monitor")]
+extern "C" {
+    fn pasillo_stop_here();
+}
+
+/// Run the monitor, or GDB's stub if it is attached, until it is continued from, e.g. at a
+/// breakpoint or watchpoint.
+#[cfg(feature = "monitor")]
+#[inline(always)]
+pub fn stop_here() {
+    unsafe { pasillo_stop_here() };
+}
+#[cfg(feature = "monitor")]
+extern "C" fn stopped() {
+    // For `bt`
+    backtrace::capture();
+    unsafe { STOPPED = true };
+    if gdb::is_attached() {
+        gdb::serve();
+    }
+    // Detaching goes back to the monitor
+    if !gdb::is_attached() {
+        unsafe { HallwayMonitor::new().interactive() };
+    }
+    unsafe { STOPPED = false };
+    backtrace::release();
+}
+/// Stop at the site `id` on `line`.
+#[cfg(feature = "monitor")]
+#[inline(never)]
+pub fn stop(id: u8, line: u32) {
+    unsafe {
+        STEP = false;
+        STOPPED_AT = Some(id);
+    }
+    BREAK_IN_REQUESTED.store(false, Ordering::Relaxed);
+    debug_println!("BREAKPOINT 0x{:02x} on line {}", id, line);
+    stop_here();
+    unsafe { STOPPED_AT = None };
+}
+/// Stop for the change [`watchpoint::check`] found, from the kernel tick. Already stopped, e.g.
+/// with interrupts enabled from the monitor, the change is left for when it continues.
+#[cfg(feature = "monitor")]
+#[inline(never)]
+pub fn stop_for_watchpoint() {
+    if !watchpoint::pending() || is_stopped() {
+        return;
+    }
+    watchpoint::report();
+    stop_here();
+}
+
+/// A breakpoint site with the `u8` ID `id`: stop in the hallway monitor here if `id` is enabled.
+/// Compiles to nothing without the `monitor` feature.
+#[allow(unused_macros)]
+macro_rules! breakpoint {
+    ($id:expr) => {
+        #[cfg(feature = "monitor")]
+        if crate::debug::breakpoint::should_break($id) {
+            crate::debug::breakpoint::stop($id, line!());
+        }
+    };
+}
+#[allow(unused_imports)]
+pub(crate) use breakpoint;
@@ -1,31 +1,91 @@
 //! Debug serial console
+//!
+//! Without the `console` feature, the console is never set, and the print macros compile to
+//! nothing, along with their format strings.
+//!
+//! With the `monitor` feature, the USART's receive interrupt takes bytes that arrive while nothing
+//! is reading (see [`crate::debug::breakpoint`]), and they are kept in [`INPUT`] for the next read.
 #![allow(unused_macros)]
 
-use crate::{shared::UsbSerial, types::string::PStackStr};
+use crate::{
+    shared::UsbSerial,
+    types::string::{pm_str, PStackStr, PmStr},
+};
 
-use core::{cell::RefCell, str::FromStr as _};
-use avr_device::interrupt::{self, Mutex};
+use core::cell::RefCell;
+use avr_device::interrupt::{self, CriticalSection, Mutex};
+use heapless::Deque;
+
+/// Bytes received outside of a read that are kept. Later ones are dropped.
+pub const INPUT_LEN: usize = 64;
 
 pub static CONSOLE: Mutex<RefCell<Option<UsbSerial>>> = interrupt::Mutex::new(RefCell::new(None));
+/// Bytes received outside of a read, oldest first.
+pub static INPUT: Mutex<RefCell<Deque<u8, INPUT_LEN>>> = Mutex::new(RefCell::new(Deque::new()));
+
+pub static DEBUG_PREFIX: PmStr = pm_str!("[debug] ");
 
 pub fn set_console(console: UsbSerial) {
+    #[cfg(not(feature = "console"))]
+    let _ = console;
+    // For Ctrl-C while the kernel runs; see `crate::debug::breakpoint`
+    #[cfg(feature = "monitor")]
+    let console = {
+        let mut console = console;
+        console.listen(arduino_hal::hal::usart::Event::RxComplete);
+        console
+    };
+    #[cfg(feature = "console")]
     interrupt::free(|cs| {
         *CONSOLE.borrow(cs).borrow_mut() = Some(console);
     })
 }
 
+/// Keep `byte`, received outside of a read, for the next read.
+pub fn buffer_input(cs: CriticalSection, byte: u8) {
+    let _ = INPUT.borrow(cs).borrow_mut().push_back(byte);
+}
+
+/// Block until a byte is read from the console, or take the oldest one in [`INPUT`].
+///
+/// # Safety
+/// See [`read_line`].
+pub unsafe fn read_byte() -> u8 {
+    interrupt::free(|cs| {
+        if let Some(byte) = INPUT.borrow(cs).borrow_mut().pop_front() {
+            return byte;
+        }
+        CONSOLE
+            .borrow(cs)
+            .borrow_mut()
+            .as_mut()
+            .unwrap()
+            .read_byte()
+    })
+}
+/// Write a byte to the console, as is.
+pub fn write_byte(byte: u8) {
+    interrupt::free(|cs| {
+        if let Some(console) = CONSOLE.borrow(cs).borrow_mut().as_mut() {
+            console.write_byte(byte);
+        }
+    })
+}
+
 /// Block until a line is read from the console, up until `LEN` characters.
 /// A "newline" is defined as a carriage return, line feed, end of file, or NUL byte.
+/// Bytes outside of ASCII are taken as Latin-1 so that the line stays valid UTF-8.
 ///
 /// # Safety
 /// This should only be used in debug situations within the kernel, as it *blocks*.
 pub unsafe fn read_line<const LEN: usize>() -> PStackStr<LEN> {
-    let mut ret = PStackStr::from_str("").unwrap();
+    let mut ret = PStackStr::new();
     interrupt::free(|cs| {
         let mut console_cell = CONSOLE.borrow(cs).borrow_mut();
         let console = console_cell.as_mut().unwrap();
-        for char in ret.inner.iter_mut() {
-            let read = console.read_byte();
+        let mut input = INPUT.borrow(cs).borrow_mut();
+        while ret.remaining() > 0 {
+            let read = input.pop_front().unwrap_or_else(|| console.read_byte());
             if read == b'\r' /* CR*/
                 || read == b'\n' /* LF */
                 || read == 0x4 /* EOF */
@@ -33,8 +93,9 @@ pub unsafe fn read_line<const LEN: usize>() -> PStackStr<LEN> {
             {
                 break;
             }
-            *char = read;
-            ret.len += 1;
+            if ret.push(char::from(read)).is_err() {
+                break;
+            }
         }
     });
     helper_print!("", "\n", "{}", ret.as_ref());
@@ -42,33 +103,35 @@ pub unsafe fn read_line<const LEN: usize>() -> PStackStr<LEN> {
 }
 
 // Print macros
+//
+// The format strings are placed in program memory through `pasillo_macros::pm_uwrite!`. Without
+// the `console` feature, they are still type checked, so that arguments count as used. Logged
+// lines not yet sent go first, so that printed text doesn't land inside one.
 
 macro_rules! helper_print {
     ($before:expr, $after:expr, $($t:tt)*) => {
-        avr_device::interrupt::free(
-            |critical_section| {
-                if let Some(console) = crate::debug::console::CONSOLE.borrow(critical_section).borrow_mut().as_mut() {
-                    let _ = ufmt::uwrite!(console, "{}", $before);
-                    let _ = ufmt::uwrite!(console, $($t)*);
-                    let _ = ufmt::uwrite!(console, "{}", $after);
-                };
-            },
-        )
+        if cfg!(feature = "console") {
+            crate::debug::log::flush();
+            avr_device::interrupt::free(
+                |critical_section| {
+                    if let Some(console) = crate::debug::console::CONSOLE.borrow(critical_section).borrow_mut().as_mut() {
+                        let _ = ufmt::uwrite!(console, "{}", $before);
+                        let _ = pasillo_macros::pm_uwrite!(console, $($t)*);
+                        let _ = ufmt::uwrite!(console, "{}", $after);
+                    };
+                },
+            )
+        }
     };
 }
-macro_rules! trace {
-    ($($t:tt)*) => {
-        crate::debug::console::helper_print!("[trace] ", '\n', $($t)*)
-    }
-}
 macro_rules! debug_println {
     ($($t:tt)*) => {
-        crate::debug::console::helper_print!("[debug] ", '\n', $($t)*)
+        crate::debug::console::helper_print!(crate::debug::console::DEBUG_PREFIX, '\n', $($t)*)
     }
 }
 macro_rules! debug_print {
     ($($t:tt)*) => {
-        crate::debug::console::helper_print!("[debug] ", "", $($t)*)
+        crate::debug::console::helper_print!(crate::debug::console::DEBUG_PREFIX, "", $($t)*)
     }
 }
 
@@ -78,5 +141,3 @@ pub(crate) use helper_print;
 pub(crate) use debug_println;
 #[allow(unused_imports)]
 pub(crate) use debug_print;
-#[allow(unused_imports)]
-pub(crate) use trace;
@@ -0,0 +1,458 @@
+//! A disassembler for the AVR instruction set, as implemented by the `atmega2560`.
+
+use crate::{
+    debug::access::{read_program, Address, AddressSpace},
+    types::string::{pm_str, PmStr},
+    utils::write_hex,
+};
+
+/// An indirect pointer operand, as used by `ld`, `st`, `lpm` and friends.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Pointer {
+    X,
+    XInc,
+    XDec,
+    Y,
+    YInc,
+    YDec,
+    /// `Y+q`
+    YDisp(u8),
+    Z,
+    ZInc,
+    ZDec,
+    /// `Z+q`
+    ZDisp(u8),
+}
+impl ufmt::uDisplay for Pointer {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized,
+    {
+        let (pre, register, post) = match self {
+            Self::X => ("", 'X', ""),
+            Self::XInc => ("", 'X', "+"),
+            Self::XDec => ("-", 'X', ""),
+            Self::Y | Self::YDisp(_) => ("", 'Y', ""),
+            Self::YInc => ("", 'Y', "+"),
+            Self::YDec => ("-", 'Y', ""),
+            Self::Z | Self::ZDisp(_) => ("", 'Z', ""),
+            Self::ZInc => ("", 'Z', "+"),
+            Self::ZDec => ("-", 'Z', ""),
+        };
+        f.write_str(pre)?;
+        f.write_char(register)?;
+        f.write_str(post)?;
+        match self {
+            Self::YDisp(displacement) | Self::ZDisp(displacement) => {
+                f.write_char('+')?;
+                ufmt::uDisplay::fmt(displacement, f)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The operands of an [`Instruction`], as they are printed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Operands {
+    None,
+    /// `rD`
+    Reg(u8),
+    /// `rD, rR`
+    RegReg(u8, u8),
+    /// `rD, 0xK`
+    RegImm(u8, u8),
+    /// `rD, b`
+    RegBit(u8, u8),
+    /// `rD, 0xA` (`in`)
+    RegIo(u8, u8),
+    /// `0xA, rR` (`out`)
+    IoReg(u8, u8),
+    /// `0xA, b`
+    IoBit(u8, u8),
+    /// `rD, <pointer>`
+    RegPtr(u8, Pointer),
+    /// `<pointer>, rR`
+    PtrReg(Pointer, u8),
+    /// `rD, 0xK` (`lds`), where `K` is a data address
+    RegData(u8, u16),
+    /// `0xK, rR` (`sts`), where `K` is a data address
+    DataReg(u16, u8),
+    /// A jump, call or branch target, as a program memory *byte* address
+    Target(u32),
+    /// A word that isn't a valid instruction
+    Word(u16),
+}
+
+/// A decoded instruction.
+#[derive(Clone, Copy)]
+pub struct Instruction {
+    pub mnemonic: PmStr,
+    pub operands: Operands,
+    /// Length in words; either 1 or 2.
+    pub words: u8,
+}
+impl Instruction {
+    const fn new(mnemonic: PmStr, operands: Operands) -> Self {
+        Self {
+            mnemonic,
+            operands,
+            words: 1,
+        }
+    }
+    const fn long(mnemonic: PmStr, operands: Operands) -> Self {
+        Self {
+            mnemonic,
+            operands,
+            words: 2,
+        }
+    }
+    /// Length in bytes.
+    pub const fn len(&self) -> u32 {
+        self.words as u32 * 2
+    }
+    /// The address this instruction jumps to, calls, or loads from or stores to directly, if any.
+    pub fn reference(&self) -> Option<Address> {
+        match self.operands {
+            Operands::Target(target) => Some(Address::new(AddressSpace::Program, target)),
+            Operands::RegData(_, addr) | Operands::DataReg(addr, _) => {
+                Some(Address::new(AddressSpace::Data, addr as u32))
+            }
+            _ => None,
+        }
+    }
+}
+impl ufmt::uDisplay for Instruction {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized,
+    {
+        ufmt::uDisplay::fmt(&self.mnemonic, f)?;
+        if self.operands == Operands::None {
+            return Ok(());
+        }
+        f.write_char(' ')?;
+        let register = |f: &mut ufmt::Formatter<'_, W>, register: u8| {
+            f.write_char('r')?;
+            ufmt::uDisplay::fmt(&register, f)
+        };
+        let hex = |f: &mut ufmt::Formatter<'_, W>, value: u32, digits: usize| {
+            f.write_str("0x")?;
+            write_hex(f, value, digits)
+        };
+        match self.operands {
+            Operands::None => Ok(()),
+            Operands::Reg(d) => register(f, d),
+            Operands::RegReg(d, r) => {
+                register(f, d)?;
+                f.write_str(", ")?;
+                register(f, r)
+            }
+            Operands::RegImm(d, k) | Operands::RegIo(d, k) => {
+                register(f, d)?;
+                f.write_str(", ")?;
+                hex(f, k as u32, 2)
+            }
+            Operands::RegBit(d, b) => {
+                register(f, d)?;
+                f.write_str(", ")?;
+                ufmt::uDisplay::fmt(&b, f)
+            }
+            Operands::IoReg(a, r) => {
+                hex(f, a as u32, 2)?;
+                f.write_str(", ")?;
+                register(f, r)
+            }
+            Operands::IoBit(a, b) => {
+                hex(f, a as u32, 2)?;
+                f.write_str(", ")?;
+                ufmt::uDisplay::fmt(&b, f)
+            }
+            Operands::RegPtr(d, pointer) => {
+                register(f, d)?;
+                f.write_str(", ")?;
+                ufmt::uDisplay::fmt(&pointer, f)
+            }
+            Operands::PtrReg(pointer, r) => {
+                ufmt::uDisplay::fmt(&pointer, f)?;
+                f.write_str(", ")?;
+                register(f, r)
+            }
+            Operands::RegData(d, k) => {
+                register(f, d)?;
+                f.write_str(", ")?;
+                hex(f, k as u32, 4)
+            }
+            Operands::DataReg(k, r) => {
+                hex(f, k as u32, 4)?;
+                f.write_str(", ")?;
+                register(f, r)
+            }
+            Operands::Target(target) => hex(f, target, 5),
+            Operands::Word(word) => hex(f, word as u32, 4),
+        }
+    }
+}
+
+/// The `bset`/`bclr` and `brbs`/`brbc` aliases, packed by `SREG` bit.
+static SREG_SET: PmStr = pm_str!("secsezsensevsessehsetsei");
+static SREG_CLEAR: PmStr = pm_str!("clcclzclnclvclsclhcltcli");
+static BRANCH_SET: PmStr = pm_str!("brcsbreqbrmibrvsbrltbrhsbrtsbrie");
+static BRANCH_CLEAR: PmStr = pm_str!("brccbrnebrplbrvcbrgebrhcbrtcbrid");
+
+/// The `bit`th alias out of one of the packed tables above.
+fn alias(table: &PmStr, bit: u16) -> PmStr {
+    let width = table.len() / 8;
+    unsafe { PmStr::from_raw_parts(table.as_ptr().add(width * bit as usize), width) }
+}
+
+/// Whether `word` is the first word of a two word instruction (`lds`, `sts`, `jmp` or `call`).
+pub const fn is_long(word: u16) -> bool {
+    word & 0xfc0f == 0x9000 || word & 0xfe0c == 0x940c
+}
+
+/// Whether the program memory byte address `addr` directly follows a call (`call`, `rcall`,
+/// `icall` or `eicall`), i.e. could be a return address.
+pub fn follows_call(addr: u32) -> bool {
+    let before = |by: u32| addr.checked_sub(by).map(read_word);
+    let call = matches!(before(4), Some(word) if word & 0xfe0e == 0x940e);
+    let short_call = matches!(
+        before(2),
+        Some(word) if word & 0xf000 == 0xd000 || word == 0x9509 || word == 0x9519
+    );
+    call || short_call
+}
+
+/// Decode the instruction at the program memory byte address `addr`, which starts with `word`.
+/// `next` is the word after it, only used by two word instructions.
+pub fn decode(addr: u32, word: u16, next: u16) -> Instruction {
+    use Instruction as I;
+    use Operands as O;
+
+    // Common operand fields
+    let d5 = ((word >> 4) & 0x1f) as u8;
+    let r5 = ((word & 0x0f) | ((word >> 5) & 0x10)) as u8;
+    let d4 = 16 + ((word >> 4) & 0x0f) as u8;
+    let k8 = ((word & 0x0f) | ((word >> 4) & 0xf0)) as u8;
+    let bit = (word & 0x07) as u8;
+    // A relative jump target, from a signed word offset
+    let relative = |offset: i32| O::Target(addr.wrapping_add_signed(2 + offset * 2));
+
+    match word {
+        0x0000 => I::new(pm_str!("nop"), O::None),
+        _ if word & 0xff00 == 0x0000 => I::new(pm_str!(".word"), O::Word(word)),
+        _ if word & 0xff00 == 0x0100 => I::new(
+            pm_str!("movw"),
+            O::RegReg(((word >> 4) & 0x0f) as u8 * 2, (word & 0x0f) as u8 * 2),
+        ),
+        _ if word & 0xff00 == 0x0200 => {
+            I::new(pm_str!("muls"), O::RegReg(d4, 16 + (word & 0x0f) as u8))
+        }
+        _ if word & 0xff00 == 0x0300 => {
+            let mnemonic = match word & 0x88 {
+                0x00 => pm_str!("mulsu"),
+                0x08 => pm_str!("fmul"),
+                0x80 => pm_str!("fmuls"),
+                _ => pm_str!("fmulsu"),
+            };
+            I::new(
+                mnemonic,
+                O::RegReg(16 + ((word >> 4) & 0x07) as u8, 16 + bit),
+            )
+        }
+        // Two register arithmetic and logic
+        _ if word & 0xc000 == 0x0000 || word & 0xfc00 == 0x9c00 => {
+            let mnemonic = match word & 0xfc00 {
+                0x0400 => pm_str!("cpc"),
+                0x0800 => pm_str!("sbc"),
+                0x0c00 if d5 == r5 => return I::new(pm_str!("lsl"), O::Reg(d5)),
+                0x0c00 => pm_str!("add"),
+                0x1000 => pm_str!("cpse"),
+                0x1400 => pm_str!("cp"),
+                0x1800 => pm_str!("sub"),
+                0x1c00 if d5 == r5 => return I::new(pm_str!("rol"), O::Reg(d5)),
+                0x1c00 => pm_str!("adc"),
+                0x2000 if d5 == r5 => return I::new(pm_str!("tst"), O::Reg(d5)),
+                0x2000 => pm_str!("and"),
+                0x2400 if d5 == r5 => return I::new(pm_str!("clr"), O::Reg(d5)),
+                0x2400 => pm_str!("eor"),
+                0x2800 => pm_str!("or"),
+                0x2c00 => pm_str!("mov"),
+                0x9c00 => pm_str!("mul"),
+                _ => pm_str!("cpi"),
+            };
+            if word & 0xf000 == 0x3000 {
+                return I::new(mnemonic, O::RegImm(d4, k8));
+            }
+            I::new(mnemonic, O::RegReg(d5, r5))
+        }
+        // Register and immediate
+        _ if word & 0xc000 == 0x4000 || word & 0xf000 == 0xe000 => {
+            let mnemonic = match word & 0xf000 {
+                0x4000 => pm_str!("sbci"),
+                0x5000 => pm_str!("subi"),
+                0x6000 => pm_str!("ori"),
+                0x7000 => pm_str!("andi"),
+                _ if k8 == 0xff => return I::new(pm_str!("ser"), O::Reg(d4)),
+                _ => pm_str!("ldi"),
+            };
+            I::new(mnemonic, O::RegImm(d4, k8))
+        }
+        // `ldd`/`std`, of which `ld`/`st` through `Y` or `Z` are the zero displacement case
+        _ if word & 0xd000 == 0x8000 => {
+            let q = ((word & 0x07) | ((word >> 7) & 0x18) | ((word >> 8) & 0x20)) as u8;
+            let pointer = match (word & 0x08 != 0, q) {
+                (true, 0) => Pointer::Y,
+                (true, q) => Pointer::YDisp(q),
+                (false, 0) => Pointer::Z,
+                (false, q) => Pointer::ZDisp(q),
+            };
+            let store = word & 0x0200 != 0;
+            match (store, q) {
+                (false, 0) => I::new(pm_str!("ld"), O::RegPtr(d5, pointer)),
+                (false, _) => I::new(pm_str!("ldd"), O::RegPtr(d5, pointer)),
+                (true, 0) => I::new(pm_str!("st"), O::PtrReg(pointer, d5)),
+                (true, _) => I::new(pm_str!("std"), O::PtrReg(pointer, d5)),
+            }
+        }
+        // Loads, stores, `push` and `pop`
+        _ if word & 0xfc00 == 0x9000 => {
+            let store = word & 0x0200 != 0;
+            let pointer = match word & 0x0f {
+                0x0 if store => return I::long(pm_str!("sts"), O::DataReg(next, d5)),
+                0x0 => return I::long(pm_str!("lds"), O::RegData(d5, next)),
+                0x1 => Pointer::ZInc,
+                0x2 => Pointer::ZDec,
+                0x4 if !store => return I::new(pm_str!("lpm"), O::RegPtr(d5, Pointer::Z)),
+                0x5 if !store => return I::new(pm_str!("lpm"), O::RegPtr(d5, Pointer::ZInc)),
+                0x6 if !store => return I::new(pm_str!("elpm"), O::RegPtr(d5, Pointer::Z)),
+                0x7 if !store => return I::new(pm_str!("elpm"), O::RegPtr(d5, Pointer::ZInc)),
+                0x9 => Pointer::YInc,
+                0xa => Pointer::YDec,
+                0xc => Pointer::X,
+                0xd => Pointer::XInc,
+                0xe => Pointer::XDec,
+                0xf if store => return I::new(pm_str!("push"), O::Reg(d5)),
+                0xf => return I::new(pm_str!("pop"), O::Reg(d5)),
+                _ => return I::new(pm_str!(".word"), O::Word(word)),
+            };
+            if store {
+                I::new(pm_str!("st"), O::PtrReg(pointer, d5))
+            } else {
+                I::new(pm_str!("ld"), O::RegPtr(d5, pointer))
+            }
+        }
+        // Operandless instructions
+        0x9409 => I::new(pm_str!("ijmp"), O::None),
+        0x9419 => I::new(pm_str!("eijmp"), O::None),
+        0x9508 => I::new(pm_str!("ret"), O::None),
+        0x9509 => I::new(pm_str!("icall"), O::None),
+        0x9518 => I::new(pm_str!("reti"), O::None),
+        0x9519 => I::new(pm_str!("eicall"), O::None),
+        0x9588 => I::new(pm_str!("sleep"), O::None),
+        0x9598 => I::new(pm_str!("break"), O::None),
+        0x95a8 => I::new(pm_str!("wdr"), O::None),
+        0x95c8 => I::new(pm_str!("lpm"), O::None),
+        0x95d8 => I::new(pm_str!("elpm"), O::None),
+        0x95e8 => I::new(pm_str!("spm"), O::None),
+        _ if word & 0xff8f == 0x9408 => I::new(alias(&SREG_SET, (word >> 4) & 0x07), O::None),
+        _ if word & 0xff8f == 0x9488 => I::new(alias(&SREG_CLEAR, (word >> 4) & 0x07), O::None),
+        // `jmp` and `call`, with a 22 bit word address
+        _ if word & 0xfe0c == 0x940c => {
+            let target = ((((word >> 3) & 0x3e) | (word & 0x01)) as u32) << 16 | next as u32;
+            let mnemonic = if word & 0x02 == 0 {
+                pm_str!("jmp")
+            } else {
+                pm_str!("call")
+            };
+            I::long(mnemonic, O::Target(target * 2))
+        }
+        // Single register
+        _ if word & 0xfe00 == 0x9400 => {
+            let mnemonic = match word & 0x0f {
+                0x0 => pm_str!("com"),
+                0x1 => pm_str!("neg"),
+                0x2 => pm_str!("swap"),
+                0x3 => pm_str!("inc"),
+                0x5 => pm_str!("asr"),
+                0x6 => pm_str!("lsr"),
+                0x7 => pm_str!("ror"),
+                0xa => pm_str!("dec"),
+                _ => return I::new(pm_str!(".word"), O::Word(word)),
+            };
+            I::new(mnemonic, O::Reg(d5))
+        }
+        _ if word & 0xfe00 == 0x9600 => {
+            let mnemonic = if word & 0x0100 == 0 {
+                pm_str!("adiw")
+            } else {
+                pm_str!("sbiw")
+            };
+            let k6 = ((word & 0x0f) | ((word >> 2) & 0x30)) as u8;
+            I::new(mnemonic, O::RegImm(24 + ((word >> 4) & 0x03) as u8 * 2, k6))
+        }
+        _ if word & 0xfc00 == 0x9800 => {
+            let mnemonic = match word & 0x0300 {
+                0x0000 => pm_str!("cbi"),
+                0x0100 => pm_str!("sbic"),
+                0x0200 => pm_str!("sbi"),
+                _ => pm_str!("sbis"),
+            };
+            I::new(mnemonic, O::IoBit(((word >> 3) & 0x1f) as u8, bit))
+        }
+        _ if word & 0xf000 == 0xb000 => {
+            let io = ((word & 0x0f) | ((word >> 5) & 0x30)) as u8;
+            if word & 0x0800 == 0 {
+                I::new(pm_str!("in"), O::RegIo(d5, io))
+            } else {
+                I::new(pm_str!("out"), O::IoReg(io, d5))
+            }
+        }
+        // `rjmp` and `rcall`, with a signed 12 bit offset
+        _ if word & 0xe000 == 0xc000 => {
+            let offset = ((word << 4) as i16 >> 4) as i32;
+            let mnemonic = if word & 0x1000 == 0 {
+                pm_str!("rjmp")
+            } else {
+                pm_str!("rcall")
+            };
+            I::new(mnemonic, relative(offset))
+        }
+        // Conditional branches, with a signed 7 bit offset
+        _ if word & 0xf800 == 0xf000 => {
+            let offset = ((word << 6) as i16 >> 9) as i32;
+            let table = if word & 0x0400 == 0 {
+                &BRANCH_SET
+            } else {
+                &BRANCH_CLEAR
+            };
+            I::new(alias(table, word & 0x07), relative(offset))
+        }
+        _ if word & 0xf808 == 0xf800 => {
+            let mnemonic = match word & 0x0600 {
+                0x0000 => pm_str!("bld"),
+                0x0200 => pm_str!("bst"),
+                0x0400 => pm_str!("sbrc"),
+                _ => pm_str!("sbrs"),
+            };
+            I::new(mnemonic, O::RegBit(d5, bit))
+        }
+        _ => I::new(pm_str!(".word"), O::Word(word)),
+    }
+}
+
+/// Read the little endian word at the program memory byte address `addr`.
+pub fn read_word(addr: u32) -> u16 {
+    u16::from_le_bytes([read_program(addr), read_program(addr + 1)])
+}
+
+/// Decode the instruction at the program memory byte address `addr`, reading it from flash.
+pub fn read(addr: u32) -> Instruction {
+    let word = read_word(addr);
+    let next = if is_long(word) {
+        read_word(addr + 2)
+    } else {
+        0
+    };
+    decode(addr, word, next)
+}
@@ -0,0 +1,274 @@
+//! A stub for GDB's remote serial protocol over the console, so that `avr-gdb` can look through the
+//! kernel where it panicked or stopped. Entered with the hallway monitor's `gdb` command.
+//!
+//! Addresses use the AVR toolchain's mapping: program memory from 0, the data space from
+//! `0x800000`, and EEPROM from `0x810000`. r0 to r31 are as saved where the kernel stopped, and
+//! changes to them take effect when it continues; SP and PC are read-only. There's no hardware
+//! single-step, so `s` continues to the next breakpoint site; see [`crate::debug::breakpoint`].
+//! Retransmissions aren't supported.
+
+use crate::debug::{
+    access::{Address, AddressSpace},
+    backtrace, breakpoint,
+    console::{read_byte, write_byte},
+    log,
+};
+
+/// Longest packet kept; the rest of a longer one is dropped (e.g. unneeded `qSupported` features).
+const PACKET_LEN: usize = 128;
+const ELF_DATA_OFFSET: u32 = 0x80_0000;
+const ELF_EEPROM_OFFSET: u32 = 0x81_0000;
+/// Offset of SREG in the `g` packet, in hex digits, after the 32 general purpose registers
+const SREG_OFFSET: usize = 32 * 2;
+const SREG: Address = Address::new(AddressSpace::Io, 0x3f);
+/// SREG's global interrupt enable bit
+const SREG_I: u8 = 1 << 7;
+
+/// Whether GDB is attached, e.g. waiting for the kernel to stop after a `c`.
+static mut ATTACHED: bool = false;
+
+pub fn is_attached() -> bool {
+    unsafe { ATTACHED }
+}
+
+fn read() -> u8 {
+    unsafe { read_byte() }
+}
+fn write(byte: u8) {
+    write_byte(byte)
+}
+
+fn parse_hex(digits: &[u8]) -> Option<u32> {
+    if digits.is_empty() {
+        return None;
+    }
+    digits.iter().try_fold(0_u32, |value, &digit| {
+        Some(value.checked_mul(16)? | (digit as char).to_digit(16)?)
+    })
+}
+/// The address for a GDB address.
+fn address(addr: u32) -> Address {
+    match addr {
+        ELF_EEPROM_OFFSET.. => Address::new(AddressSpace::Eeprom, addr - ELF_EEPROM_OFFSET),
+        ELF_DATA_OFFSET.. => Address::new(AddressSpace::Data, addr - ELF_DATA_OFFSET),
+        _ => Address::new(AddressSpace::Program, addr),
+    }
+}
+/// Parse `<addr>,<len>`, checking that the range is within its address space.
+fn parse_range(args: &[u8]) -> Option<(Address, u32)> {
+    let comma = args.iter().position(|&byte| byte == b',')?;
+    let start = address(parse_hex(&args[..comma])?);
+    let len = parse_hex(&args[comma + 1..])?;
+    (start.addr.checked_add(len)? <= start.space.size()).then_some((start, len))
+}
+
+struct Packet {
+    data: [u8; PACKET_LEN],
+    len: usize,
+}
+impl Packet {
+    /// Block until a packet with a valid checksum is received, and acknowledge it.
+    fn receive(&mut self) {
+        loop {
+            // Acknowledgements and anything else between packets are skipped
+            while read() != b'$' {}
+            self.len = 0;
+            let mut checksum = 0_u8;
+            loop {
+                let byte = read();
+                if byte == b'#' {
+                    break;
+                }
+                checksum = checksum.wrapping_add(byte);
+                if self.len < PACKET_LEN {
+                    self.data[self.len] = byte;
+                    self.len += 1;
+                }
+            }
+            if parse_hex(&[read(), read()]) == Some(checksum as u32) {
+                write(b'+');
+                return;
+            }
+            write(b'-');
+        }
+    }
+}
+
+/// A reply packet, sent as it is built.
+struct Reply {
+    checksum: u8,
+}
+impl Reply {
+    fn new() -> Self {
+        write(b'$');
+        Self { checksum: 0 }
+    }
+    fn byte(&mut self, byte: u8) {
+        write(byte);
+        self.checksum = self.checksum.wrapping_add(byte);
+    }
+    fn str(&mut self, text: &str) {
+        text.bytes().for_each(|byte| self.byte(byte));
+    }
+    fn hex(&mut self, value: u8) {
+        self.byte(hex_digit(value >> 4));
+        self.byte(hex_digit(value & 0xf));
+    }
+    fn send(self) {
+        write(b'#');
+        write(hex_digit(self.checksum >> 4));
+        write(hex_digit(self.checksum & 0xf));
+    }
+}
+fn hex_digit(nibble: u8) -> u8 {
+    char::from_digit(nibble as u32, 16).unwrap_or_default() as u8
+}
+fn reply(text: &str) {
+    let mut reply = Reply::new();
+    reply.str(text);
+    reply.send();
+}
+
+/// Why the kernel stopped: SIGTRAP at a breakpoint or watchpoint, SIGABRT if it panicked.
+fn send_stop() {
+    reply(if breakpoint::is_stopped() {
+        "S05"
+    } else {
+        "S06"
+    });
+}
+/// `g`: r0 to r31, SREG, SP and PC, each little endian.
+fn send_registers() {
+    let stack_pointer = backtrace::panic_stack_pointer().unwrap_or_else(backtrace::stack_pointer);
+    // Where it stopped, as the innermost return address that follows a call
+    let mut pc = 0;
+    let end = backtrace::stack_end(stack_pointer);
+    backtrace::walk(stack_pointer, end, |frame| {
+        if frame.after_call {
+            pc = frame.ret.addr;
+        }
+        frame.after_call
+    });
+
+    let mut reply = Reply::new();
+    backtrace::registers()
+        .iter()
+        .for_each(|&byte| reply.hex(byte));
+    reply.hex(SREG.read().unwrap_or_default());
+    stack_pointer
+        .to_le_bytes()
+        .iter()
+        .for_each(|&byte| reply.hex(byte));
+    pc.to_le_bytes().iter().for_each(|&byte| reply.hex(byte));
+    reply.send();
+}
+/// `G`: r0 to r31 and SREG can be written, SREG without its interrupt bit so that the stub isn't
+/// interrupted.
+fn write_registers(args: &[u8]) {
+    let Some(digits) = args.get(..SREG_OFFSET) else {
+        return reply("E01");
+    };
+    let mut registers = [0; 32];
+    for (register, digits) in registers.iter_mut().zip(digits.chunks(2)) {
+        let Some(value) = parse_hex(digits) else {
+            return reply("E01");
+        };
+        *register = value as u8;
+    }
+    backtrace::set_registers(registers);
+    if let Some(sreg) = args.get(SREG_OFFSET..SREG_OFFSET + 2).and_then(parse_hex) {
+        let interrupts = SREG.read().unwrap_or_default() & SREG_I;
+        let _ = unsafe { SREG.write((sreg as u8 & !SREG_I) | interrupts) };
+    }
+    reply("OK");
+}
+/// `m<addr>,<len>`
+fn read_memory(args: &[u8]) {
+    let Some((start, len)) = parse_range(args) else {
+        return reply("E01");
+    };
+    let mut reply = Reply::new();
+    (0..len).for_each(|idx| reply.hex(start.offset(idx as i32).read().unwrap_or_default()));
+    reply.send();
+}
+/// `M<addr>,<len>:<bytes>`
+fn write_memory(args: &[u8]) {
+    let colon = args
+        .iter()
+        .position(|&byte| byte == b':')
+        .unwrap_or(args.len());
+    let (range, bytes) = args.split_at(colon);
+    let Some((start, len)) = parse_range(range) else {
+        return reply("E01");
+    };
+    let bytes = bytes.get(1..).unwrap_or_default();
+    if bytes.len() != len as usize * 2 {
+        return reply("E01");
+    }
+    for (idx, digits) in bytes.chunks(2).enumerate() {
+        let Some(byte) = parse_hex(digits) else {
+            return reply("E01");
+        };
+        // e.g. program memory is read-only
+        if unsafe { start.offset(idx as i32).write(byte as u8) }.is_err() {
+            return reply("E02");
+        }
+    }
+    reply("OK");
+}
+
+/// Serve GDB until it continues or detaches. Continuing is only possible when stopped at a
+/// breakpoint or watchpoint, after which GDB stays attached and this should be called again
+/// wherever the kernel stops next.
+pub fn serve() {
+    // Nothing logged may go out in the middle of a packet
+    log::flush();
+    let paused = log::pause();
+    serve_packets();
+    log::resume(paused);
+}
+fn serve_packets() {
+    // Continued before, so GDB is waiting to hear why it stopped
+    if is_attached() {
+        send_stop();
+    }
+    unsafe { ATTACHED = true };
+    let mut packet = Packet {
+        data: [0; PACKET_LEN],
+        len: 0,
+    };
+    loop {
+        packet.receive();
+        let Some((&command, args)) = packet.data[..packet.len].split_first() else {
+            reply("");
+            continue;
+        };
+        match command {
+            b'?' => send_stop(),
+            b'g' => send_registers(),
+            b'G' => write_registers(args),
+            b'm' => read_memory(args),
+            b'M' => write_memory(args),
+            b'c' | b's' if breakpoint::is_stopped() => {
+                if command == b's' {
+                    breakpoint::step();
+                }
+                return;
+            }
+            // A panic can't be continued from
+            b'c' | b's' => send_stop(),
+            b'D' => {
+                unsafe { ATTACHED = false };
+                return reply("OK");
+            }
+            b'k' => {
+                unsafe { ATTACHED = false };
+                return;
+            }
+            b'H' => reply("OK"),
+            b'q' if args.starts_with(b"Supported") => reply("PacketSize=80"),
+            // Unsupported
+            _ => reply(""),
+        }
+    }
+}
@@ -1,39 +1,87 @@
 //! An interactive "debugger" or "monitor," [`HallwayMonitor`], meant for debug scenarios to look
 //! through memory.
 
+#[cfg(feature = "monitor")]
 use crate::debug::console::helper_print;
-#[cfg(debug_assertions)]
+#[cfg(feature = "monitor")]
 use crate::{
+    crash,
+    debug::access::{Address, AddressSpace},
+    debug::backtrace,
+    debug::breakpoint,
     debug::console::{debug_print as print, debug_println as println, read_line},
+    debug::disasm,
+    debug::gdb,
+    debug::log::{self, Level},
     debug::memory::MARKERS,
-    utils::decompose_uninit_array,
+    debug::protocol,
+    debug::registers::{self, Register},
+    debug::symbols::{self, Annotation},
+    debug::watchpoint,
+    panic::{self, PanicPolicy},
+    task::{
+        scheduler::{Task, TASKS},
+        stack::STACK_LEN,
+        state::TaskState,
+    },
+    types::array::PStackArr,
 };
+#[cfg(feature = "monitor")]
+use core::mem::size_of;
 
-#[cfg(debug_assertions)]
+#[cfg(feature = "monitor")]
 use avr_device::interrupt;
+#[cfg(feature = "monitor")]
 use avr_progmem::progmem;
 
-#[cfg(debug_assertions)]
+#[cfg(feature = "monitor")]
 progmem! {
     static progmem string HELP_MESSAGE =
     r#"Commands:
     help/h - <--
-    exit/e - <--
+    exit/e - <--, continuing if stopped at a breakpoint or watchpoint
+    c - [c]ontinue from a breakpoint or watchpoint
+    n - continue to the [n]ext breakpoint site, enabled or not
+    crash - the last saved crash record
+    policy(<POLICY>) - the panic policy, or set it: monitor/halt/blink/reboot <SECS>/default
+    dmesg - recent log lines
+    log((<MODULE>) <LEVEL>) - log filters, or set one: off/error/warn/info/debug/trace
+    bp(<0xID>) - list enabled [b]reak[p]oints, or toggle one; Ctrl-C stops at the next site
     cli - [cl]ear [i]nterrupts
     ena - [ena]ble interrupts
-    r<0xLEN> - [r]ead memory
+    r<0xLEN>(@<ADDR>) - [r]ead (hex dump) LEN bytes
+    s"TEXT"(,<0xLEN>) - [s]earch for text in the next LEN bytes
+    s<0xBYTES>(,<0xLEN>) - [s]earch for bytes, i.e. `s0xdeadbeef`
+    s - [s]earch again, after the current hit; in r:, from 0x200 to the end of SRAM, skipping
+        the monitor's own stack
     w<0xBYTE>(,<0bBYTE>...) - [w]rite bytes
     cw<0xBYTE>,0xLEN - [c]opy [w]rite
-    j - [j]ump to a function pointer
+    watch(<ADDR>,<0xLEN>) - list watchpoints, or [watch] LEN bytes of RAM, checked every tick
+    unwatch<0xIDX> - remove the watchpoint numbered IDX by `watch`
+    reg - list named [reg]isters
+    reg <NAME> - show a [reg]ister and its bits, e.g. `reg TCCR1B`
+    reg <NAME>(.<BIT>)=<0xVAL> - write a [reg]ister or one of its bits, e.g. `reg PORTB.7=1`
+    d(<ADDR>)(,<0xCOUNT>) - [d]isassemble COUNT instructions, e.g. `d0x1f4,10`
+    j - [j]ump to the function at a p: position below 0x20000; check it with `d` first
+    bt(<ADDR>) - [b]ack[t]race from a stack pointer, e.g. as shown by `t`, or from the panic;
+        `?` marks return addresses that don't follow a call, which are likely stale
+    t(<0xID>) - list [t]asks, or show one's registers where it stopped and the stack it has used
     a<0xLEN> - [a]dvance pointer
     b<0xLEN> - [b]acktrack pointer
     lm - [l]ist [m]arkers
-    m<name> - point to [m]arker
-    g<0xPOS> - [g]oto position"#;
+    ls(<TEXT>) - [l]ist [s]ymbols, or only those containing TEXT
+    m<NAME> - point to [m]arker or symbol NAME, or to the marker numbered NAME by `lm`
+    g<ADDR> - [g]oto position, e.g. `g main`
+    gdb - hand the console to `avr-gdb` (`target remote`) until it detaches
+ADDR is a marker or symbol NAME(+<0xOFF>), or (<SPACE>:)<0xPOS>, where SPACE is one of
+    r - RAM (data space, the default, except for `d`)
+    p - program memory (flash, read-only)
+    e - EEPROM
+    i - I/O space (data space minus 0x20)"#;
 }
 
 /// Helper method to parse `input`[`offset`..`offset+len`] to a `usize` in `radix`
-#[cfg(debug_assertions)]
+#[cfg(feature = "monitor")]
 fn helper_parse(input: &str, offset: usize, len: usize, radix: u32, default: usize) -> usize {
     let parse = input.split_at_checked(offset).unwrap_or(("", "")).1;
     let parse = parse.split_at_checked(len).unwrap_or((parse, "")).0;
@@ -41,23 +89,136 @@ fn helper_parse(input: &str, offset: usize, len: usize, radix: u32, default: usi
     usize::from_str_radix(parse, radix).unwrap_or(default)
 }
 
-/// An interactive "debugger" to examine memory. Only available with debug assertions.
-#[cfg(debug_assertions)]
-#[derive(Default)]
+/// Longest pattern that can be searched for
+#[cfg(feature = "monitor")]
+const SEARCH_LEN: usize = 16;
+
+#[cfg(feature = "monitor")]
+#[derive(Clone, Default)]
+struct Search {
+    pattern: PStackArr<u8, SEARCH_LEN>,
+    /// How many bytes to search through. Defaults to the rest of the address space, or of the
+    /// internal SRAM.
+    len: Option<u32>,
+}
+/// Where the internal SRAM starts, after the registers. RAM searches start there, as reading some
+/// registers has side effects, e.g. `UDR0` pops a received byte.
+#[cfg(feature = "monitor")]
+const SRAM_START: u32 = 0x200;
+
+/// Helper method to parse a search of the form `"TEXT"(,<0xLEN>)` or `<0xBYTES>(,<0xLEN>)`
+#[cfg(feature = "monitor")]
+fn helper_parse_search(input: &str) -> Option<Search> {
+    let mut search = Search::default();
+    let len = match input.strip_prefix('"') {
+        Some(text) => {
+            let (text, rest) = text.split_once('"').unwrap_or((text, ""));
+            for byte in text.bytes() {
+                search.pattern.push(byte).ok()?;
+            }
+            rest
+        }
+        None => {
+            let (bytes, rest) = input.split_once(',').unwrap_or((input, ""));
+            let bytes = bytes.trim_start_matches("0x");
+            if bytes.len() % 2 != 0 {
+                return None;
+            }
+            for idx in (0..bytes.len()).step_by(2) {
+                let byte = u8::from_str_radix(bytes.get(idx..idx + 2)?, 16).ok()?;
+                search.pattern.push(byte).ok()?;
+            }
+            rest
+        }
+    };
+    let len = len.trim_start_matches(',').trim_start_matches("0x");
+    if !len.is_empty() {
+        search.len = Some(u32::from_str_radix(len, 16).ok()?);
+    }
+    (!search.pattern.is_empty()).then_some(search)
+}
+
+/// Helper method to parse an address of the form `(<space>:)<0xPOS>` or `NAME(+<0xOFF>)` from
+/// `input`[`offset`..]. Without a space prefix, the space of `default` (or the data space) is used.
+#[cfg(feature = "monitor")]
+fn helper_parse_address(input: &str, offset: usize, default: Option<Address>) -> Option<Address> {
+    let parse = input.split_at_checked(offset).unwrap_or(("", "")).1.trim();
+    helper_parse_position(parse, default).or_else(|| {
+        let (name, by) = parse.split_once('+').unwrap_or((parse, "0"));
+        let by = u16::from_str_radix(by.trim_start_matches("0x"), 16).ok()?;
+        symbols::address_of(name).map(|address| address.offset(by as i32))
+    })
+}
+/// The `(<space>:)<0xPOS>` half of [`helper_parse_address`]
+#[cfg(feature = "monitor")]
+fn helper_parse_position(parse: &str, default: Option<Address>) -> Option<Address> {
+    let (space, parse) = match parse.split_once(':') {
+        Some((prefix, rest)) => {
+            let mut prefix = prefix.chars();
+            match (
+                prefix.next().and_then(AddressSpace::from_prefix),
+                prefix.next(),
+            ) {
+                (Some(space), None) => (space, rest),
+                _ => return None,
+            }
+        }
+        None => (
+            default
+                .map(|address| address.space)
+                .unwrap_or(AddressSpace::Data),
+            parse,
+        ),
+    };
+    let parse = parse.trim_start_matches("0x");
+    match u32::from_str_radix(parse, 16) {
+        Ok(addr) => Some(Address::new(space, addr)),
+        // Only switch the address space, e.g. `gp:`
+        Err(_) if parse.is_empty() => Some(Address::new(
+            space,
+            default.map(|address| address.addr).unwrap_or_default(),
+        )),
+        Err(_) => None,
+    }
+}
+
+/// An interactive "debugger" to examine memory. Only available with the `monitor` feature.
+#[cfg(feature = "monitor")]
 pub struct HallwayMonitor {
-    pos: Option<*const u8>,
+    pos: Option<Address>,
+    last_search: Option<Search>,
+    /// The stack pointer of whatever started the monitor. The monitor's own frames are below it.
+    stack_pointer: u16,
 }
-#[cfg(debug_assertions)]
+#[cfg(feature = "monitor")]
 impl HallwayMonitor {
+    /// Inlined, so that it sees its caller's stack pointer.
+    #[inline(always)]
     pub fn new() -> Self {
-        Self::default()
+        Self {
+            pos: None,
+            last_search: None,
+            stack_pointer: backtrace::stack_pointer(),
+        }
+    }
+    /// Whether the data address `addr` is the monitor's own, and so holds copies of a search's
+    /// pattern: its stack below [`Self::stack_pointer`], live or not, or the monitor itself.
+    fn is_own(&self, addr: u32) -> bool {
+        let top = self.stack_pointer as u32;
+        let bottom =
+            (backtrace::stack_end(self.stack_pointer) as u32).saturating_sub(STACK_LEN as u32);
+        let this = self as *const Self as usize as u32;
+        (bottom..=top).contains(&addr) || (this..this + size_of::<Self>() as u32).contains(&addr)
     }
     /// Run an interactive hallway monitor.
     ///
     /// # Safety
     /// This disables interrupts and blocks everything while waiting for user input.
     pub unsafe fn interactive(&mut self) {
-        interrupt::disable_save();
+        let irq = interrupt::disable_save();
+        // Before the monitor's output, which is all that goes to the console until it's left
+        log::flush();
+        let paused = log::pause();
         println!("Welcome to Hallway Monitor!");
         self.help();
         loop {
@@ -66,24 +227,62 @@ impl HallwayMonitor {
             let input_stack_str = unsafe { read_line::<96>() };
             let input = input_stack_str.as_ref().trim();
             match input {
+                // Host tools, see `protocol`
+                _binary if input == protocol::MAGIC => protocol::serve(&mut self.pos),
                 _help if input.starts_with('h') => self.help(),
-                _exit if input.starts_with('e') => break,
                 _clear_interrupts if input.starts_with("cli") => {
                     interrupt::disable_save();
                 }
                 _enable_interrupts if input.starts_with("ena") => unsafe {
                     interrupt::enable();
                 },
-                _read_memory if input.starts_with('r') => unsafe {
-                    self.read_memory(helper_parse(input, 1, 8, 16, 8))
+                _exit if input.starts_with('e') => break,
+                _continue if input == "c" || input == "n" => {
+                    if !breakpoint::is_stopped() {
+                        println!("Not stopped at a breakpoint or watchpoint\nHINT: `exit` anyway");
+                        continue;
+                    }
+                    if input == "n" {
+                        breakpoint::step();
+                    }
+                    break;
+                }
+                _crash if input == "crash" => match crash::load() {
+                    Some(record) => crash::print(record),
+                    None => println!("No crash recorded"),
                 },
+                _dmesg if input == "dmesg" => log::dmesg(),
+                _log if input.starts_with("log") => self.log(input[3..].trim()),
+                _policy if input.starts_with("policy") => self.policy(input[6..].trim()),
+                _register if input.starts_with("reg") => unsafe { self.register(&input[3..]) },
+                _read_memory if input.starts_with('r') => {
+                    let (len, at) = input.split_once('@').unwrap_or((input, ""));
+                    if !at.is_empty() {
+                        match helper_parse_address(at, 0, self.pos) {
+                            Some(address) => self.pos = Some(address),
+                            None => {
+                                println!("invalid address `{}`", at);
+                                continue;
+                            }
+                        }
+                    }
+                    self.read_memory(helper_parse(len, 1, 8, 16, 0x40))
+                }
+                _search if input.starts_with('s') => self.search(&input[1..]),
+                _watch if input.starts_with("watch") => self.watch(input[5..].trim()),
+                _unwatch if input.starts_with("unwatch") => {
+                    let idx = helper_parse(input[7..].trim(), 0, 4, 16, usize::MAX);
+                    if let Err(error) = watchpoint::remove(idx) {
+                        println!("{}", error);
+                    }
+                }
                 _write_memory if input.starts_with('w') => unsafe {
                     let original_pos = self.pos;
                     let mut offset = 0;
                     while offset < input.len() {
                         offset += 1;
-                        let len = match input_stack_str.inner[offset + 1] {
-                            b'x' => 4,
+                        let len = match input.as_bytes().get(offset + 1) {
+                            Some(b'x') => 4,
                             _ => 2,
                         };
                         self.write_memory(helper_parse(input, offset, len, 16, 0));
@@ -94,8 +293,8 @@ impl HallwayMonitor {
                 },
                 _copy_write_memory if input.starts_with("cw") => unsafe {
                     let original_pos = self.pos;
-                    let len = match input_stack_str.inner[3] {
-                        b'x' => 4,
+                    let len = match input.as_bytes().get(3) {
+                        Some(b'x') => 4,
                         _ => 2,
                     };
                     let byte = helper_parse(input, 2, len, 16, 0);
@@ -105,41 +304,95 @@ impl HallwayMonitor {
                     });
                     self.pos = original_pos;
                 },
-                _jump if input.starts_with('j') => {
-                    if let Some(ptr) = self.pos {
-                        let trampoline: fn() -> ! = unsafe { core::mem::transmute(ptr) };
+                _disassemble if input.starts_with('d') => self.disassemble(&input[1..]),
+                _jump if input.starts_with('j') => match self.pos {
+                    Some(Address {
+                        space: AddressSpace::Program,
+                        addr,
+                    }) if addr >= 1 << 17 => {
+                        println!("Can only jump to the first 128 KiB, without `EIND`");
+                    }
+                    Some(Address {
+                        space: AddressSpace::Program,
+                        addr,
+                    }) => {
+                        // Function pointers are 16-bit word addresses
+                        let trampoline: fn() -> ! =
+                            unsafe { core::mem::transmute((addr / 2) as usize) };
                         trampoline();
                     }
-                }
+                    _ => println!("Can only jump to program memory\nHINT: `gp:<0xPOS>`"),
+                },
+                _tasks if input.starts_with('t') => self.tasks(input[1..].trim()),
                 _forward if input.starts_with('a') => {
                     self.advance(helper_parse(input, 1, 8, 16, 1))
                 }
+                _breakpoints if input.starts_with("bp") => self.breakpoints(input[2..].trim()),
+                _backtrace if input.starts_with("bt") => self.backtrace(&input[2..]),
                 _back if input.starts_with('b') => self.backtrack(helper_parse(input, 1, 8, 16, 1)),
                 _list_markers if input.starts_with("lm") => self.list_markers(),
-                _marker if input.starts_with('m') => unsafe {
-                    self.marker(helper_parse(input, 1, 8, 16, 0))
-                },
-                _goto if input.starts_with('g') => {
-                    self.pos = Some(helper_parse(
-                        input,
-                        1,
-                        8,
-                        16,
-                        self.pos.map(|ptr| ptr.addr()).unwrap_or_default(),
-                    ) as *const u8)
+                _list_symbols if input.starts_with("ls") => self.list_symbols(input[2..].trim()),
+                _marker if input.starts_with('m') => self.marker(input[1..].trim()),
+                _gdb if input == "gdb" => {
+                    println!("Handing the console to GDB");
+                    gdb::serve();
+                    // Still attached if continued
+                    if gdb::is_attached() {
+                        break;
+                    }
                 }
+                _goto if input.starts_with('g') => match helper_parse_address(input, 1, self.pos) {
+                    Some(address) => self.pos = Some(address),
+                    None => println!("invalid address `{}`", input),
+                },
                 _ => println!("invalid input `{}`\nsee `help`", input),
             }
         }
-        unsafe { interrupt::enable() }
+        log::resume(paused);
+        // As they were, so that a breakpoint in a critical section continues in it
+        unsafe { interrupt::restore(irq) }
+    }
+
+    fn policy(&self, input: &str) {
+        let policy = match input.split_once(' ').unwrap_or((input, "")) {
+            ("", _) => return println!("Panic policy: {}", panic::policy()),
+            ("monitor", _) => Some(PanicPolicy::Monitor),
+            ("halt", _) => Some(PanicPolicy::Halt),
+            ("blink", _) => Some(PanicPolicy::Blink),
+            ("reboot", seconds) => match seconds.trim().parse() {
+                Ok(seconds) => Some(PanicPolicy::Reboot(seconds)),
+                Err(_) => return println!("invalid seconds `{}`", seconds),
+            },
+            ("default", _) => None,
+            _ => return println!("invalid policy `{}`\nsee `help`", input),
+        };
+        panic::set_policy(policy);
+        println!("Panic policy: {}", panic::policy());
     }
 
+    fn log(&self, input: &str) {
+        if input.is_empty() {
+            return log::for_each_filter(|module, level| {
+                println!("{}: {}", module.unwrap_or("*"), level)
+            });
+        }
+        let (module, level) = match input.rsplit_once(' ') {
+            Some((module, level)) => (Some(module.trim()), level),
+            None => (None, input),
+        };
+        let Some(level) = Level::from_name(level) else {
+            return println!("invalid level `{}`\nsee `help`", level);
+        };
+        if let Err(error) = log::set_level(module, level) {
+            println!("{}", error);
+        }
+    }
     fn help(&self) {
         println!("{}", HELP_MESSAGE);
     }
     fn status(&self) {
         match self.pos {
-            Some(ptr) => println!("Position: 0x{:x}", ptr.addr()),
+            Some(address) => println!("Position: {}{}", address, Annotation(address)),
             None => {
                 println!(
                     "No position specified.\nHINT: Point to a marker first!\nHINT: See `help`."
@@ -147,55 +400,474 @@ impl HallwayMonitor {
             }
         }
     }
-    unsafe fn read_memory(&self, len: usize) {
-        let ptr = match self.pos {
-            Some(ptr) => ptr,
+    /// Print `len` bytes from the current position as a hex dump: the address, 16 bytes, and an
+    /// ASCII gutter per row.
+    fn read_memory(&self, len: usize) {
+        let start = match self.pos {
+            Some(address) => address,
             None => return,
         };
-        (0..len * 8).for_each(|offset| {
-            let pos = unsafe { ptr.add(offset) };
-            helper_print!("", "", "0x{:02x} ", unsafe { *pos });
-            if offset != 0 && (offset + 1) % 8 == 0 {
-                helper_print!("", '\n', "<-- @0x{:x}", pos.addr() + 1);
+        let mut row = [0_u8; 16];
+        for row_offset in (0..len).step_by(row.len()) {
+            let row_start = start.offset(row_offset as i32);
+            let row_len = (len - row_offset).min(row.len());
+            for (idx, byte) in row[..row_len].iter_mut().enumerate() {
+                let address = row_start.offset(idx as i32);
+                match address.read() {
+                    Ok(read) => *byte = read,
+                    Err(error) => {
+                        println!("{} @{}{}", error, address, Annotation(address));
+                        return;
+                    }
+                }
+            }
+
+            helper_print!(
+                "",
+                "",
+                "{}:{:05x} ",
+                row_start.space.prefix(),
+                row_start.addr
+            );
+            for idx in 0..row.len() {
+                if idx == row.len() / 2 {
+                    helper_print!("", "", " ");
+                }
+                if idx < row_len {
+                    helper_print!("", "", " {:02x}", row[idx]);
+                } else {
+                    helper_print!("", "", "   ");
+                }
             }
+            helper_print!("", "", "  |");
+            row[..row_len].iter().for_each(|byte| {
+                let char = if byte.is_ascii_graphic() || *byte == b' ' {
+                    *byte as char
+                } else {
+                    '.'
+                };
+                helper_print!("", "", "{}", char);
+            });
+            helper_print!("", '\n', "|{}", Annotation(row_start));
+        }
+    }
+    /// Disassemble `(<ADDR>)(,<0xCOUNT>)` instructions, moving the position to the start. Jump,
+    /// call and `lds`/`sts` targets are annotated with the marker or symbol they point to, and
+    /// symbols are labelled where they start.
+    fn disassemble(&mut self, args: &str) {
+        let (at, count) = args.split_once(',').unwrap_or((args, ""));
+        // Disassembly defaults to program memory, starting at the position if it is there
+        let default = match self.pos {
+            Some(address) if address.space == AddressSpace::Program => address,
+            _ => Address::new(AddressSpace::Program, 0),
+        };
+        let start = match at.trim() {
+            "" => default,
+            at => match helper_parse_address(at, 0, Some(default)) {
+                Some(address) if address.space == AddressSpace::Program => address,
+                _ => {
+                    println!("invalid address `{}`\nHINT: only program memory", at);
+                    return;
+                }
+            },
+        };
+        // Stay at the start, so that what was shown is what `j` jumps to
+        self.pos = Some(start);
+
+        let mut addr = start.addr;
+        for _ in 0..helper_parse(count, 0, 8, 16, 8) {
+            if addr >= AddressSpace::Program.size() {
+                break;
+            }
+            let address = Address::new(AddressSpace::Program, addr);
+            match symbols::resolve(address) {
+                Some((_, offset)) if addr == start.addr || offset == 0 => {
+                    println!("{}:", Annotation(address))
+                }
+                _ => {}
+            }
+            let instruction = disasm::read(addr);
+            helper_print!("", "", "p:{:05x}  {:04x} ", addr, disasm::read_word(addr));
+            if instruction.words == 2 {
+                helper_print!("", "", "{:04x}  ", disasm::read_word(addr + 2));
+            } else {
+                helper_print!("", "", "      ");
+            }
+            helper_print!("", "", "{}", instruction);
+            if let Some(reference) = instruction.reference() {
+                helper_print!("", "", "{}", Annotation(reference));
+            }
+            helper_print!("", '\n', "");
+            addr += instruction.len();
+        }
+    }
+    /// Show, or with `NAME(.BIT)=VAL`, write a named register.
+    unsafe fn register(&self, args: &str) {
+        let args = args.trim();
+        if args.is_empty() {
+            let mut count = 0;
+            registers::for_each(|register| {
+                count += 1;
+                helper_print!(
+                    "",
+                    if count % 8 == 0 { "\n" } else { " " },
+                    "{}",
+                    register.name()
+                );
+                false
+            });
+            helper_print!("", '\n', "");
+            return;
+        }
+
+        let (target, value) = match args.split_once('=') {
+            Some((target, value)) => (target.trim(), Some(value.trim())),
+            None => (args, None),
+        };
+        let (name, bit) = match target.split_once('.') {
+            Some((name, bit)) => (name, Some(bit)),
+            None => (target, None),
+        };
+        let Some(register) = registers::lookup(name) else {
+            println!("Unknown register `{}`\nHINT: `reg` lists them all", name);
+            return;
+        };
+        let bit = match bit.map(|bit| register.bit(bit)) {
+            Some(None) => {
+                println!(
+                    "`{}` has no bit `{}`",
+                    register.name(),
+                    bit.unwrap_or_default()
+                );
+                return;
+            }
+            Some(Some(bit)) => Some(bit),
+            None => None,
+        };
+
+        if let Some(value) = value {
+            let Ok(value) = u16::from_str_radix(value.trim_start_matches("0x"), 16) else {
+                println!("invalid value `{}`", value);
+                return;
+            };
+            let written = match bit {
+                Some(_) if value > 1 => {
+                    println!("A bit can only be set to 0 or 1");
+                    return;
+                }
+                // Writing back a flag as read would clear it, or toggle a `PINx` bit
+                Some(bit) => register.read().and_then(|old| {
+                    let old = old & !register.write_one_bits() & !(1 << bit);
+                    unsafe { register.write(old | value << bit) }
+                }),
+                None => unsafe { register.write(value) },
+            };
+            if let Err(error) = written {
+                println!("{}", error);
+                return;
+            }
+        }
+        self.show_register(&register);
+    }
+    fn show_register(&self, register: &Register) {
+        let value = match register.read() {
+            Ok(value) => value,
+            Err(error) => {
+                println!("{}", error);
+                return;
+            }
+        };
+        if register.is_wide() {
+            println!(
+                "{} @{} = 0x{:04x}",
+                register.name(),
+                register.address(),
+                value
+            );
+        } else {
+            println!(
+                "{} @{} = 0x{:02x}",
+                register.name(),
+                register.address(),
+                value
+            );
+        }
+        let mut bits = register.bits().peekable();
+        if bits.peek().is_none() {
+            return;
+        }
+        bits.for_each(|(bit, name)| helper_print!(" ", "", "{}={}", name, (value >> bit) & 1));
+        helper_print!("", '\n', "");
+    }
+    /// Search for a pattern from the current position, moving to the first hit. Without `args`,
+    /// the last search is continued after the current position.
+    fn search(&mut self, args: &str) {
+        let Some(pos) = self.pos else {
+            println!("No position specified.\nHINT: Search starts at the position");
+            return;
+        };
+        let start = if args.is_empty() {
+            pos.offset(1)
+        } else {
+            match helper_parse_search(args) {
+                Some(search) => self.last_search = Some(search),
+                None => {
+                    println!("invalid search `{}`\nsee `help`", args);
+                    return;
+                }
+            }
+            pos
+        };
+        let Some(search) = &self.last_search else {
+            println!("Nothing to search for\nsee `help`");
+            return;
+        };
+
+        let pattern = search.pattern.as_slice();
+        let data = start.space == AddressSpace::Data;
+        let (start, size) = if data {
+            let start = Address::new(AddressSpace::Data, start.addr.max(SRAM_START));
+            (start, backtrace::RAMEND as u32 + 1)
+        } else {
+            (start, start.space.size())
+        };
+        let end = match search.len {
+            Some(len) => start.addr.saturating_add(len),
+            None => size,
+        }
+        .min(size);
+        let hit = (start.addr..end.saturating_sub(pattern.len() as u32 - 1))
+            .map(|addr| Address::new(start.space, addr))
+            .find(|address| {
+                pattern.iter().enumerate().all(|(idx, byte)| {
+                    let at = address.offset(idx as i32);
+                    !(data && self.is_own(at.addr)) && at.read().is_ok_and(|read| read == *byte)
+                })
+            });
+        match hit {
+            Some(address) => {
+                println!("Found at {}{}", address, Annotation(address));
+                self.pos = Some(address);
+            }
+            None => println!("Not found before {}", Address::new(start.space, end)),
+        }
+    }
+    /// Print the return addresses on the stack, starting from the data address in `args`, or else
+    /// where the kernel panicked.
+    fn backtrace(&self, args: &str) {
+        let stack_pointer = match args.trim() {
+            "" => backtrace::panic_stack_pointer().unwrap_or_else(backtrace::stack_pointer),
+            at => match helper_parse_address(at, 0, None) {
+                Some(Address {
+                    space: AddressSpace::Data,
+                    addr,
+                }) => addr as u16,
+                _ => {
+                    println!("invalid stack pointer `{}`", at);
+                    return;
+                }
+            },
+        };
+        let end = backtrace::stack_end(stack_pointer);
+        println!("Stack r:0x{:x}..=r:0x{:x}", stack_pointer, end);
+        let mut idx = 0;
+        backtrace::walk(stack_pointer, end, |frame| {
+            println!(
+                "#{} {}: {}{}{}",
+                idx,
+                frame.at,
+                frame.ret,
+                Annotation(frame.ret),
+                if frame.after_call { "" } else { " ?" }
+            );
+            idx += 1;
+            false
         });
+        if idx == 0 {
+            println!("No return addresses found");
+        }
     }
+    /// List the enabled breakpoints, or with an `ID`, toggle that breakpoint.
+    fn breakpoints(&self, args: &str) {
+        if args.is_empty() {
+            if let Some(id) = breakpoint::stopped_at() {
+                println!("Stopped at 0x{:02x}", id);
+            }
+            print!("Enabled:");
+            breakpoint::enabled().for_each(|id| helper_print!(" ", "", "0x{:02x}", id));
+            helper_print!("", "\n", "");
+            return;
+        }
+        let id = helper_parse(args, 0, 4, 16, usize::MAX);
+        if id >= breakpoint::NUM_BREAKPOINTS {
+            println!("invalid breakpoint `{}`", args);
+            return;
+        }
+        let enabled = !breakpoint::is_enabled(id as u8);
+        breakpoint::set_enabled(id as u8, enabled);
+        println!(
+            "Breakpoint 0x{:02x} {}",
+            id,
+            if enabled { "enabled" } else { "disabled" }
+        );
+    }
+    /// List the watchpoints, or with `<ADDR>,<0xLEN>`, add one.
+    fn watch(&self, args: &str) {
+        if args.is_empty() {
+            watchpoint::for_each(|idx, watchpoint| {
+                println!(
+                    "{} --> {},0x{:x}{}",
+                    idx,
+                    watchpoint.start,
+                    watchpoint.len,
+                    Annotation(watchpoint.start)
+                )
+            });
+            return;
+        }
+        let (at, len) = args.split_once(',').unwrap_or((args, ""));
+        let Some(start) = helper_parse_address(at.trim(), 0, self.pos) else {
+            println!("invalid address `{}`", at);
+            return;
+        };
+        match watchpoint::add(start, helper_parse(len.trim(), 0, 4, 16, 1)) {
+            Ok(()) => println!("Watching {}{}", start, Annotation(start)),
+            Err(error) => println!("{}", error),
+        }
+    }
+    /// List the tasks, or with an `ID`, dump the part of one's stack it has used.
+    fn tasks(&mut self, args: &str) {
+        if args.is_empty() {
+            unsafe { TASKS.iter() }.for_each(|task| self.show_task(task));
+            return;
+        }
+        let id = helper_parse(args, 0, 4, 16, usize::MAX);
+        let Some(task) = unsafe { TASKS.iter() }.find(|task| task.id as usize == id) else {
+            println!("No task `{}`\nHINT: `t` lists them all", args);
+            return;
+        };
+        self.show_task(task);
+        // Only the task that was running when the kernel stopped has its registers saved
+        if task.state == TaskState::Running && backtrace::panic_stack_pointer().is_some() {
+            println!("Registers where it stopped:");
+            for (row, registers) in backtrace::registers().chunks(8).enumerate() {
+                print!("r{}..r{}", row * 8, row * 8 + 7);
+                registers
+                    .iter()
+                    .for_each(|register| helper_print!(" ", "", "{:02x}", *register));
+                helper_print!("", '\n', "");
+            }
+        }
+
+        let top = if task.state == TaskState::Running {
+            // This may be the stack being run on
+            let stack_pointer =
+                backtrace::panic_stack_pointer().unwrap_or_else(backtrace::stack_pointer);
+            (task.stack_start.addr()..task.stack_end.addr())
+                .contains(&(stack_pointer as usize))
+                .then_some(stack_pointer as usize)
+        } else {
+            // Nothing switches tasks out yet, so all there is to go by is the fill pattern
+            let used = task.high_water_mark();
+            (used > 0).then(|| task.stack_end.addr() - used - 1)
+        };
+        let Some(top) = top else {
+            println!("Nothing on its stack");
+            return;
+        };
+        // The stack pointer points to the next free byte
+        let start = Address::new(AddressSpace::Data, top as u32 + 1);
+        println!(
+            "Stack (HINT: `bt {}`):",
+            Address::new(AddressSpace::Data, top as u32)
+        );
+        self.pos = Some(start);
+        self.read_memory(task.stack_end.addr().saturating_sub(start.addr as usize));
+    }
+    fn show_task(&self, task: &Task) {
+        // The running task's stack top is wherever the stack pointer is now, or where it stopped
+        let stack_pointer =
+            backtrace::panic_stack_pointer().unwrap_or_else(backtrace::stack_pointer) as usize;
+        let top = if task.state == TaskState::Running
+            && (task.stack_start.addr()..task.stack_end.addr()).contains(&stack_pointer)
+        {
+            stack_pointer
+        } else {
+            task.stack_top.addr()
+        };
+        println!(
+            "#{} {} stack {}..{} top {} used {}/{} cpu {}ms",
+            task.id,
+            task.state,
+            Address::data(task.stack_start),
+            Address::data(task.stack_end),
+            Address::new(AddressSpace::Data, top as u32),
+            task.high_water_mark(),
+            task.stack_len(),
+            task.cpu_time / 1000
+        );
+    }
+    /// Write to the current position. Writes to program memory are refused, and writes to EEPROM go
+    /// through its write sequence.
     unsafe fn write_memory(&self, byte: usize) {
-        let ptr = match self.pos {
-            Some(ptr) => ptr,
+        let address = match self.pos {
+            Some(address) => address,
             None => return,
         };
-        *ptr.cast_mut() = byte.try_into().unwrap_or(u8::MAX);
+        if let Err(error) = unsafe { address.write(byte.try_into().unwrap_or(u8::MAX)) } {
+            println!("{} @{}{}", error, address, Annotation(address));
+        }
     }
-    /// Advance the pointer by `by` bits.
+    /// Advance the pointer by `by` bytes.
     fn advance(&mut self, by: usize) {
-        self.pos = unsafe { self.pos.map(|ptr| ptr.add(by)) };
+        self.pos = self.pos.map(|address| address.offset(by as i32));
     }
-    /// Backtrack the pointer by `by` bits.
+    /// Backtrack the pointer by `by` bytes.
     fn backtrack(&mut self, by: usize) {
-        self.pos = unsafe { self.pos.map(|ptr| ptr.sub(by)) };
+        self.pos = self.pos.map(|address| address.offset(-(by as i32)));
     }
     fn list_markers(&self) {
-        unsafe { &decompose_uninit_array(MARKERS.inner)[..MARKERS.len] }
-            .iter()
+        unsafe { MARKERS.iter() }
             .enumerate()
-            .for_each(|(idx, (name, _))| println!("{} --> {}", idx, name))
+            .for_each(|(idx, (name, address))| println!("{} --> {} @{}", idx, name, address))
     }
-    unsafe fn marker(&mut self, idx: usize) {
-        if idx >= unsafe { MARKERS.len } {
-            println!("Marker not found\nHINT: Use `lm` to list markers");
+    /// List the symbols whose name contains `filter`.
+    fn list_symbols(&self, filter: &str) {
+        if symbols::is_empty() {
+            println!(
+                "No symbols embedded\nHINT: Rebuild with PASILLO_SYMBOLS_ELF=<copy of the ELF>"
+            );
             return;
         }
-        let (name, pos) = unsafe { MARKERS.inner[idx] }.assume_init();
-        println!("Pointing to marker `{}`!", name);
-        self.pos = Some(pos);
+        symbols::iter()
+            .filter(|symbol| {
+                symbol
+                    .name
+                    .load::<{ symbols::MAX_NAME_LEN }>()
+                    .as_str()
+                    .contains(filter)
+            })
+            .for_each(|symbol| println!("{} {}", symbol.address, symbol.name));
+    }
+    /// Point to the marker or symbol `name`, or to marker number `name` as listed by `lm`.
+    fn marker(&mut self, name: &str) {
+        let address = symbols::address_of(name).or_else(|| {
+            let idx = usize::from_str_radix(name, 16).ok()?;
+            unsafe { MARKERS.get(idx) }.map(|(_, address)| *address)
+        });
+        let Some(address) = address else {
+            println!("Marker not found\nHINT: Use `lm` or `ls` to list markers and symbols");
+            return;
+        };
+        println!("Pointing to {}{}!", address, Annotation(address));
+        self.pos = Some(address);
     }
 }
 
 /// A dummy hallway monitor
-#[cfg(not(debug_assertions))]
+#[cfg(not(feature = "monitor"))]
 pub struct HallwayMonitor;
-#[cfg(not(debug_assertions))]
+#[cfg(not(feature = "monitor"))]
 impl HallwayMonitor {
     pub fn new() -> Self {
         Self {}
@@ -0,0 +1,565 @@
+//! Leveled kernel logging into a ring buffer, drained to the console in the background.
+//!
+//! [`error!`], [`warn!`], [`info!`], [`debug!`] and [`trace!`] add a line of
+//! `[<ticks>] <LEVEL> <module>: <message>` to the ring, where it stays for `dmesg` until newer
+//! lines overwrite it. Logging never waits on the console: it only starts [`drain`]ing, where the
+//! USART's data register empty interrupt sends the ring a byte at a time, whenever the USART can
+//! take one, until everything has been sent. Before anything is printed directly to the console,
+//! [`flush`] sends the rest right away, so that printed text never lands inside a line or record.
+//!
+//! While something else owns the console, e.g. the hallway monitor or GDB's stub, the ring isn't
+//! drained at all; see [`pause`].
+//!
+//! A line is kept if its level passes both the compile-time filter, [`LOG_LEVEL`] or a module's
+//! entry in [`LOG_FILTERS`], and the runtime one set with [`set_level`]. Lines filtered out at
+//! compile time aren't built in at all. Modules are named without the crate, e.g. `task::tick`,
+//! and a filter for a module covers the modules inside it.
+//!
+//! With the `binary-log` feature, a line is a record instead: the format string is interned in the
+//! `.pasillo_log` section, which only the ELF keeps (see `log.x`), and the record has its address
+//! and the arguments' raw bytes. Each record is [`FRAME_START`], then COBS encoded
+//! `level: u8, ticks: u32, format: u16, arguments...` ending in a 0 byte. An argument is a type
+//! tag and its bytes, see [`Encode`]. `pasillo-ctl log` decodes records back into lines, and
+//! passes through the rest of the console's output.
+//!
+//! A stale ELF would decode into the wrong text, so at boot [`write_image_record`] logs a record
+//! that identifies the flashed image, for `pasillo-ctl log` to check its ELF against.
+#![allow(unused_macros)]
+
+use crate::{
+    debug::console::CONSOLE,
+    shared::{LOG_FILTERS, LOG_LEVEL},
+    task::tick,
+    types::{
+        array::PStackArr,
+        error::{PError, PErrorVariant, PStaticResult},
+        string::{pm_str, PStackStr, PmStr},
+    },
+};
+
+#[cfg(feature = "binary-log")]
+use crate::{
+    debug::access::{read_program, text_end},
+    utils::{crc16_update, CRC16_INIT},
+};
+
+use avr_device::interrupt::{self, Mutex};
+use core::{
+    cell::RefCell,
+    convert::Infallible,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use embedded_hal::serial::Write;
+
+/// Bytes of recent lines kept. A power of two, so that wrapping around is cheap.
+pub const RING_LEN: usize = 512;
+/// Modules that can have their own runtime filter.
+pub const NUM_FILTERS: usize = 4;
+/// Longest module name in a runtime filter.
+pub const MODULE_LEN: usize = 24;
+const CRATE_PREFIX: &str = "pasillo::";
+/// What each line (or record) in the ring ends with
+#[cfg(not(feature = "binary-log"))]
+const LINE_END: u8 = b'\n';
+#[cfg(feature = "binary-log")]
+const LINE_END: u8 = 0;
+/// Starts a binary record, as it never appears in text
+#[cfg(feature = "binary-log")]
+pub const FRAME_START: u8 = 0x1b;
+/// Longest binary record before COBS encoding. Arguments past it are dropped.
+#[cfg(feature = "binary-log")]
+pub const RECORD_LEN: usize = 48;
+/// Takes the level's place in the record from [`write_image_record`]
+#[cfg(feature = "binary-log")]
+pub const IMAGE_RECORD: u8 = 0xff;
+
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    /// Filters everything out
+    Off = 0,
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+impl Level {
+    pub const ALL: [Self; 6] = [
+        Self::Off,
+        Self::Error,
+        Self::Warn,
+        Self::Info,
+        Self::Debug,
+        Self::Trace,
+    ];
+
+    pub fn name(self) -> PmStr {
+        match self {
+            Self::Off => pm_str!("OFF"),
+            Self::Error => pm_str!("ERROR"),
+            Self::Warn => pm_str!("WARN"),
+            Self::Info => pm_str!("INFO"),
+            Self::Debug => pm_str!("DEBUG"),
+            Self::Trace => pm_str!("TRACE"),
+        }
+    }
+    /// The level named `name`, in any case, e.g. `warn`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|level| {
+            level
+                .name()
+                .bytes()
+                .eq(name.bytes().map(|byte| byte.to_ascii_uppercase()))
+        })
+    }
+}
+impl ufmt::uDisplay for Level {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized,
+    {
+        ufmt::uDisplay::fmt(&self.name(), f)
+    }
+}
+
+/// `module` without the crate's name.
+const fn strip_crate(module: &str) -> &[u8] {
+    let (module, prefix) = (module.as_bytes(), CRATE_PREFIX.as_bytes());
+    if starts_with(module, prefix) {
+        module.split_at(prefix.len()).1
+    } else {
+        module
+    }
+}
+const fn starts_with(text: &[u8], prefix: &[u8]) -> bool {
+    if prefix.len() > text.len() {
+        return false;
+    }
+    let mut idx = 0;
+    while idx < prefix.len() {
+        if text[idx] != prefix[idx] {
+            return false;
+        }
+        idx += 1;
+    }
+    true
+}
+/// Whether `module` is `filter`, or inside it.
+const fn in_module(module: &[u8], filter: &[u8]) -> bool {
+    starts_with(module, filter)
+        && (module.len() == filter.len()
+            || (module.len() > filter.len() + 1
+                && module[filter.len()] == b':'
+                && module[filter.len() + 1] == b':'))
+}
+
+/// The compile-time filter for `module`: its first entry in [`LOG_FILTERS`], or [`LOG_LEVEL`].
+pub const fn compiled_level(module: &str) -> Level {
+    let module = strip_crate(module);
+    let mut idx = 0;
+    while idx < LOG_FILTERS.len() {
+        let (filter, level) = LOG_FILTERS[idx];
+        if in_module(module, filter.as_bytes()) {
+            return level;
+        }
+        idx += 1;
+    }
+    LOG_LEVEL
+}
+
+/// The runtime filter for modules without their own.
+static mut LEVEL: Level = LOG_LEVEL;
+static mut FILTERS: PStackArr<(PStackStr<MODULE_LEN>, Level), NUM_FILTERS> = PStackArr::new();
+
+/// The runtime filter for `module`.
+pub fn level(module: &str) -> Level {
+    let module = strip_crate(module);
+    unsafe { FILTERS.iter() }
+        .find(|(filter, _)| in_module(module, filter.as_bytes()))
+        .map_or(unsafe { LEVEL }, |(_, level)| *level)
+}
+/// Set the runtime filter for `module`, or for every module without their own if `None`.
+pub fn set_level(module: Option<&str>, level: Level) -> PStaticResult<()> {
+    let Some(module) = module else {
+        unsafe { LEVEL = level };
+        return Ok(());
+    };
+    let module = module.strip_prefix(CRATE_PREFIX).unwrap_or(module);
+    if let Some((_, filter_level)) =
+        unsafe { FILTERS.iter_mut() }.find(|(filter, _)| filter.as_ref() == module)
+    {
+        *filter_level = level;
+        return Ok(());
+    }
+    let filter = module
+        .parse()
+        .map_err(|_| PError::new(PErrorVariant::Overflow, pm_str!("module name too long")))?;
+    unsafe { FILTERS.push((filter, level)) }
+        .map_err(|_| PError::new(PErrorVariant::OutOfMemory, pm_str!("too many filters")))
+}
+/// The default runtime filter, then each module's.
+pub fn for_each_filter(mut f: impl FnMut(Option<&str>, Level)) {
+    f(None, unsafe { LEVEL });
+    unsafe { FILTERS.iter() }.for_each(|(filter, level)| f(Some(filter.as_ref()), *level));
+}
+#[inline(always)]
+pub fn enabled(module: &str, level: Level) -> bool {
+    level as u8 <= self::level(module) as u8
+}
+
+/// Recent lines, as a byte stream that wraps around.
+pub struct Ring {
+    bytes: [u8; RING_LEN],
+    /// Bytes ever written; the next goes at `written % RING_LEN`
+    written: u32,
+    /// Bytes ever sent to the console
+    sent: u32,
+}
+impl Ring {
+    fn get(&self, idx: u32) -> u8 {
+        self.bytes[idx as usize % RING_LEN]
+    }
+    /// Where the bytes still in the ring start.
+    fn oldest(&self) -> u32 {
+        self.written.saturating_sub(RING_LEN as u32)
+    }
+    fn push(&mut self, byte: u8) {
+        self.bytes[self.written as usize % RING_LEN] = byte;
+        self.written += 1;
+        // Unsent bytes that were overwritten are dropped
+        self.sent = self.sent.max(self.oldest());
+    }
+}
+impl ufmt::uWrite for Ring {
+    type Error = Infallible;
+    fn write_str(&mut self, text: &str) -> Result<(), Infallible> {
+        text.bytes().for_each(|byte| self.push(byte));
+        Ok(())
+    }
+}
+
+static RING: Mutex<RefCell<Ring>> = Mutex::new(RefCell::new(Ring {
+    bytes: [0; RING_LEN],
+    written: 0,
+    sent: 0,
+}));
+
+/// Add a line to the ring; see [`log!`].
+#[cfg(not(feature = "binary-log"))]
+pub fn write(module: &str, level: Level, message: impl FnOnce(&mut Ring)) {
+    let ticks = tick::ticks();
+    let module = module.strip_prefix(CRATE_PREFIX).unwrap_or(module);
+    interrupt::free(|cs| {
+        let ring = &mut *RING.borrow(cs).borrow_mut();
+        let _ = pasillo_macros::pm_uwrite!(ring, "[{}] {} {}: ", ticks, level, module);
+        message(ring);
+        let _ = ufmt::uWrite::write_char(ring, '\n');
+    });
+    drain();
+}
+
+/// A binary record being built; see [`log!`].
+#[cfg(feature = "binary-log")]
+pub struct Record {
+    bytes: [u8; RECORD_LEN],
+    len: usize,
+}
+#[cfg(feature = "binary-log")]
+impl Record {
+    /// Add as much of `bytes` as fits.
+    pub fn push(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            let Some(slot) = self.bytes.get_mut(self.len) else {
+                return;
+            };
+            *slot = byte;
+            self.len += 1;
+        }
+    }
+    /// Add a string argument: its length as a `u8`, then as much of it as fits.
+    fn push_str(&mut self, bytes: impl Iterator<Item = u8>) {
+        let len_idx = self.len;
+        self.push(&[0]);
+        let start = self.len;
+        bytes
+            .take(u8::MAX as usize)
+            .for_each(|byte| self.push(&[byte]));
+        if let Some(len) = self.bytes.get_mut(len_idx) {
+            *len = (self.len - start) as u8;
+        }
+    }
+}
+
+/// An argument of a binary record, as a type tag and raw bytes:
+///
+/// | tag | type | bytes |
+/// |-----|------|-------|
+/// | 0, 1, 2 | `u8`, `u16`, `u32` | little endian |
+/// | 3, 4, 5 | `i8`, `i16`, `i32` | little endian |
+/// | 6 | `bool` | 0 or 1 |
+/// | 7 | `char` | `u32`, little endian |
+/// | 8 | `&str`, [`PmStr`] | length as a `u8`, then UTF-8 |
+#[cfg(feature = "binary-log")]
+pub trait Encode {
+    fn encode(&self, record: &mut Record);
+}
+#[cfg(feature = "binary-log")]
+macro_rules! impl_encode {
+    ($($type:ty => $tag:literal),*) => {
+        $(impl Encode for $type {
+            fn encode(&self, record: &mut Record) {
+                record.push(&[$tag]);
+                record.push(&self.to_le_bytes());
+            }
+        })*
+    };
+}
+#[cfg(feature = "binary-log")]
+impl_encode!(u8 => 0, u16 => 1, u32 => 2, i8 => 3, i16 => 4, i32 => 5);
+#[cfg(feature = "binary-log")]
+impl Encode for bool {
+    fn encode(&self, record: &mut Record) {
+        record.push(&[6, *self as u8]);
+    }
+}
+#[cfg(feature = "binary-log")]
+impl Encode for char {
+    fn encode(&self, record: &mut Record) {
+        record.push(&[7]);
+        record.push(&(*self as u32).to_le_bytes());
+    }
+}
+#[cfg(feature = "binary-log")]
+impl Encode for &str {
+    fn encode(&self, record: &mut Record) {
+        record.push(&[8]);
+        record.push_str(self.bytes());
+    }
+}
+#[cfg(feature = "binary-log")]
+impl Encode for PmStr {
+    fn encode(&self, record: &mut Record) {
+        record.push(&[8]);
+        record.push_str(self.bytes());
+    }
+}
+
+/// `text` as an array, to intern a format string; see [`log!`].
+#[cfg(feature = "binary-log")]
+pub const fn intern<const LEN: usize>(text: &str) -> [u8; LEN] {
+    let mut bytes = [0; LEN];
+    let mut idx = 0;
+    while idx < LEN {
+        bytes[idx] = text.as_bytes()[idx];
+        idx += 1;
+    }
+    bytes
+}
+
+/// Add a binary record to the ring; see [`log!`]. `format` is the interned format string's address.
+#[cfg(feature = "binary-log")]
+pub fn write_record(format: u16, level: Level, arguments: impl FnOnce(&mut Record)) {
+    let mut record = Record {
+        bytes: [0; RECORD_LEN],
+        len: 0,
+    };
+    record.push(&[level as u8]);
+    record.push(&tick::ticks().to_le_bytes());
+    record.push(&format.to_le_bytes());
+    arguments(&mut record);
+    push_record(&record.bytes[..record.len]);
+}
+/// Log the record that identifies the flashed image: [`IMAGE_RECORD`], then the text section's
+/// length as a `u32` and its CRC-16, as `pasillo-ctl log` works them out from the ELF. Reading all
+/// of the text takes a while, so this is only done once, at boot.
+#[cfg(feature = "binary-log")]
+pub fn write_image_record() {
+    let len = text_end();
+    let crc = (0..len).fold(CRC16_INIT, |crc, addr| {
+        crc16_update(crc, read_program(addr))
+    });
+    let [a, b, c, d] = len.to_le_bytes();
+    let [low, high] = crc.to_le_bytes();
+    push_record(&[IMAGE_RECORD, a, b, c, d, low, high]);
+}
+/// Add a COBS encoded record to the ring.
+#[cfg(feature = "binary-log")]
+fn push_record(record: &[u8]) {
+    interrupt::free(|cs| {
+        let ring = &mut *RING.borrow(cs).borrow_mut();
+        ring.push(FRAME_START);
+        // Records are shorter than 0xfe bytes, so each block ends in a zero or the end
+        for block in record.split(|&byte| byte == 0) {
+            ring.push(block.len() as u8 + 1);
+            block.iter().for_each(|&byte| ring.push(byte));
+        }
+        ring.push(0);
+    });
+    drain();
+}
+
+/// Whether something else owns the console; see [`pause`].
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// Stop sending the ring to the console, e.g. while GDB's stub owns it, until [`resume`]. Returns
+/// whether it was paused already, for `resume`.
+pub fn pause() -> bool {
+    let paused = PAUSED.load(Ordering::Relaxed);
+    PAUSED.store(true, Ordering::Relaxed);
+    paused
+}
+/// Undo [`pause`], with what it returned, and send what can be sent if no longer paused.
+pub fn resume(paused: bool) {
+    PAUSED.store(paused, Ordering::Relaxed);
+    drain();
+}
+
+/// Send the next unsent byte, if there is one and the console can take it. Each byte is sent in
+/// its own critical section, so that sending the ring never holds up the tick for long.
+fn send_next(block: bool) -> bool {
+    interrupt::free(|cs| {
+        let ring = &mut *RING.borrow(cs).borrow_mut();
+        let mut console = CONSOLE.borrow(cs).borrow_mut();
+        let Some(console) = console.as_mut() else {
+            return false;
+        };
+        if PAUSED.load(Ordering::Relaxed) || ring.sent >= ring.written {
+            return false;
+        }
+        let byte = ring.get(ring.sent);
+        if block {
+            console.write_byte(byte);
+        } else if console.write(byte).is_err() {
+            return false;
+        }
+        ring.sent += 1;
+        true
+    })
+}
+/// Start sending what hasn't been sent yet in the background, from [`USART0_UDRE`]. Safe anywhere,
+/// including interrupts and critical sections, where sending starts once interrupts are enabled.
+pub fn drain() {
+    if PAUSED.load(Ordering::Relaxed) {
+        return;
+    }
+    let usart = unsafe { &*arduino_hal::pac::USART0::ptr() };
+    interrupt::free(|_| usart.ucsr0b.modify(|_, w| w.udrie0().set_bit()));
+}
+/// Send the next byte whenever the USART's data register is empty, and stop once there is none
+/// to send, or while paused.
+#[avr_device::interrupt(atmega2560)]
+fn USART0_UDRE() {
+    if !send_next(false) {
+        let usart = unsafe { &*arduino_hal::pac::USART0::ptr() };
+        usart.ucsr0b.modify(|_, w| w.udrie0().clear_bit());
+    }
+}
+/// Block until everything has been sent, unless paused.
+pub fn flush() {
+    while send_next(true) {}
+}
+/// Print every line still in the ring, sent or not.
+pub fn dmesg() {
+    let mut idx = interrupt::free(|cs| {
+        let ring = &*RING.borrow(cs).borrow();
+        let mut start = ring.oldest();
+        // Skip the rest of a line that was partly overwritten
+        if start > 0 {
+            while start < ring.written && ring.get(start) != LINE_END {
+                start += 1;
+            }
+            start += 1;
+        }
+        start
+    });
+    // A byte at a time, as with `send_next`
+    while interrupt::free(|cs| {
+        let ring = &*RING.borrow(cs).borrow();
+        let mut console = CONSOLE.borrow(cs).borrow_mut();
+        let Some(console) = console.as_mut() else {
+            return false;
+        };
+        // Lines logged meanwhile may have overwritten the oldest ones
+        idx = idx.max(ring.oldest());
+        if idx >= ring.written {
+            return false;
+        }
+        console.write_byte(ring.get(idx));
+        idx += 1;
+        true
+    }) {}
+}
+
+// Log macros
+//
+// As text, the format strings are placed in program memory through `pasillo_macros::pm_uwrite!`.
+// As binary records, they are interned with their module, as `<module>\x1f<format>\0`, and only
+// take the 2 bytes of their address in a record. Arguments must implement `Encode`.
+
+#[cfg(not(feature = "binary-log"))]
+macro_rules! log {
+    ($level:expr, $($t:tt)*) => {{
+        const COMPILED: bool =
+            $level as u8 <= crate::debug::log::compiled_level(module_path!()) as u8;
+        if COMPILED && crate::debug::log::enabled(module_path!(), $level) {
+            crate::debug::log::write(module_path!(), $level, |ring| {
+                let _ = pasillo_macros::pm_uwrite!(ring, $($t)*);
+            });
+        }
+    }};
+}
+#[cfg(feature = "binary-log")]
+macro_rules! log {
+    ($level:expr, $format:literal $(, $argument:expr)* $(,)?) => {{
+        const COMPILED: bool =
+            $level as u8 <= crate::debug::log::compiled_level(module_path!()) as u8;
+        if COMPILED && crate::debug::log::enabled(module_path!(), $level) {
+            const TEXT: &str = concat!(module_path!(), "\x1f", $format, "\0");
+            #[link_section = ".pasillo_log"]
+            static FORMAT: [u8; TEXT.len()] = crate::debug::log::intern(TEXT);
+            let format = core::ptr::addr_of!(FORMAT) as u16;
+            crate::debug::log::write_record(format, $level, |_record| {
+                $(crate::debug::log::Encode::encode(&$argument, _record);)*
+            });
+        }
+    }};
+}
+macro_rules! error {
+    ($($t:tt)*) => {
+        crate::debug::log::log!(crate::debug::log::Level::Error, $($t)*)
+    }
+}
+macro_rules! warn {
+    ($($t:tt)*) => {
+        crate::debug::log::log!(crate::debug::log::Level::Warn, $($t)*)
+    }
+}
+macro_rules! info {
+    ($($t:tt)*) => {
+        crate::debug::log::log!(crate::debug::log::Level::Info, $($t)*)
+    }
+}
+macro_rules! debug {
+    ($($t:tt)*) => {
+        crate::debug::log::log!(crate::debug::log::Level::Debug, $($t)*)
+    }
+}
+macro_rules! trace {
+    ($($t:tt)*) => {
+        crate::debug::log::log!(crate::debug::log::Level::Trace, $($t)*)
+    }
+}
+
+#[allow(unused_imports)]
+pub(crate) use debug;
+#[allow(unused_imports)]
+pub(crate) use error;
+#[allow(unused_imports)]
+pub(crate) use info;
+#[allow(unused_imports)]
+pub(crate) use log;
+#[allow(unused_imports)]
+pub(crate) use trace;
+#[allow(unused_imports)]
+pub(crate) use warn;
@@ -1,31 +1,24 @@
 //! A global table of pointers to known "markers."
 
-use crate::types::array::PStackArrUnchecked;
+use crate::{debug::access::Address, types::array::PStackArr};
 
-#[cfg(debug_assertions)]
-use core::mem::MaybeUninit;
-
-pub type MarkerEntry = (&'static str, *const u8);
-#[cfg(debug_assertions)]
+pub type MarkerEntry = (&'static str, Address);
+#[cfg(feature = "markers")]
 pub const NUM_MARKERS: usize = 16;
-#[cfg(not(debug_assertions))]
+#[cfg(not(feature = "markers"))]
 pub const NUM_MARKERS: usize = 0;
-pub static mut MARKERS: PStackArrUnchecked<MarkerEntry, NUM_MARKERS> = PStackArrUnchecked::new();
+pub static mut MARKERS: PStackArr<MarkerEntry, NUM_MARKERS> = PStackArr::new();
 
 /// Add a marker to the global `MARKERS` array. If the array is full, nothing will occur.
 #[inline(never)]
 #[require_unsafe_in_body]
-#[cfg(debug_assertions)]
-pub unsafe fn add_marker_manual(name: &'static str, ptr: *const u8) {
-    if unsafe { MARKERS.len } >= NUM_MARKERS {
-        return;
-    }
-    unsafe { MARKERS.inner[MARKERS.len] = MaybeUninit::new((name, ptr)) };
-    unsafe { MARKERS.len += 1 };
+#[cfg(feature = "markers")]
+pub unsafe fn add_marker_manual(name: &'static str, address: Address) {
+    let _ = unsafe { MARKERS.push((name, address)) };
 }
-#[cfg(not(debug_assertions))]
+#[cfg(not(feature = "markers"))]
 #[require_unsafe_in_body]
-pub unsafe fn add_marker_manual(_: &'static str, _: *const u8) {}
+pub unsafe fn add_marker_manual(_: &'static str, _: Address) {}
 
 #[allow(unused_macros)]
 macro_rules! add_marker {
@@ -35,7 +28,7 @@ macro_rules! add_marker {
         unsafe {
             crate::debug::memory::add_marker_manual(
                 $name,
-                core::ptr::addr_of!($marker) as *const u8,
+                crate::debug::access::Address::data(core::ptr::addr_of!($marker) as *const u8),
             )
         }
     };
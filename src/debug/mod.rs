@@ -1,5 +1,22 @@
 //! Debug utilities.
 
+pub mod access;
+#[cfg(feature = "monitor")]
+pub mod backtrace;
+pub mod breakpoint;
+#[cfg(feature = "monitor")]
+pub mod disasm;
+#[cfg(feature = "monitor")]
+pub mod gdb;
 pub mod hallway;
+pub mod log;
 pub mod memory;
+#[cfg(feature = "monitor")]
+pub mod protocol;
+#[cfg(feature = "monitor")]
+pub mod registers;
+#[cfg(feature = "monitor")]
+pub mod symbols;
+#[cfg(feature = "monitor")]
+pub mod watchpoint;
 pub mod console;
\ No newline at end of file
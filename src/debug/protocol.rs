@@ -0,0 +1,245 @@
+//! A binary request/response mode of the hallway monitor, for host tools to drive it reliably.
+//!
+//! The monitor switches to it on a line of [`MAGIC`]. Each message is a COBS encoded frame ending
+//! in a 0 byte. Decoded, a request is `opcode, seq, payload..., crc` and its response is
+//! `opcode, seq, payload..., crc`, where `seq` is echoed back and `crc` is the CRC-16/CCITT-FALSE
+//! of everything before it, little endian. A failed request's response has [`ERROR`] set in its
+//! opcode, and the [`crate::types::error::PErrorVariant`] code as its payload. Frames that are
+//! corrupted or too long are answered with a [`NACK`].
+//!
+//! Addresses are 5 bytes: the address space prefix (e.g. `r`), then the address as a `u32`.
+//! Multi-byte integers are little endian.
+
+use crate::{
+    debug::{
+        access::{Address, AddressSpace},
+        backtrace,
+        console::{read_byte, write_byte},
+        memory::MARKERS,
+        registers::{self, Register},
+    },
+    types::{
+        error::{PError, PErrorVariant, PStaticResult},
+        string::pm_str,
+    },
+    utils::crc16,
+};
+
+/// Switches the monitor to binary mode. The escape makes it unlikely to be typed.
+pub const MAGIC: &str = "\x1bpasillo-bin";
+pub const VERSION: u8 = 1;
+
+/// Longest decoded frame.
+pub const FRAME_LEN: usize = 80;
+/// Most bytes read or written by one request.
+pub const MAX_DATA_LEN: usize = 64;
+/// Set in a response's opcode if the request failed.
+pub const ERROR: u8 = 0x80;
+/// The response to a frame that couldn't be decoded.
+pub const NACK: u8 = 0xff;
+
+/// `-> version`
+pub const HELLO: u8 = 0x00;
+/// `address, len: u8 -> bytes`
+pub const READ: u8 = 0x01;
+/// `address, bytes ->`
+pub const WRITE: u8 = 0x02;
+/// `address ->`, moving the monitor's position
+pub const GOTO: u8 = 0x03;
+/// `idx: u8 -> count: u8, address, name`
+pub const MARKER: u8 = 0x04;
+/// `idx: u16 -> address, wide: u8, value: u16, name`
+pub const REGISTER: u8 = 0x05;
+/// `->`, back to the text monitor
+pub const EXIT: u8 = 0x06;
+/// `-> stopped: u8, stack_pointer: u16`, where the kernel panicked or stopped
+pub const STACK_POINTER: u8 = 0x07;
+
+/// Block until a frame is read, and decode it in place. `None` if it is corrupt or too long.
+fn receive(frame: &mut [u8; FRAME_LEN]) -> Option<usize> {
+    let mut len = 0;
+    let mut overflowed = false;
+    loop {
+        let byte = unsafe { read_byte() };
+        if byte == 0 {
+            break;
+        }
+        if len < frame.len() {
+            frame[len] = byte;
+            len += 1;
+        } else {
+            overflowed = true;
+        }
+    }
+    if overflowed {
+        return None;
+    }
+
+    // Decoding never outgrows the encoding, so it can be done in place
+    let (mut read, mut written) = (0, 0);
+    while read < len {
+        let code = frame[read] as usize;
+        if code == 0 || read + code > len {
+            return None;
+        }
+        frame.copy_within(read + 1..read + code, written);
+        written += code - 1;
+        read += code;
+        // A code of 0xff is a full block without a zero after it, as is the last block
+        if code != 0xff && read < len {
+            frame[written] = 0;
+            written += 1;
+        }
+    }
+    if written < 4 {
+        return None;
+    }
+    let crc = u16::from_le_bytes([frame[written - 2], frame[written - 1]]);
+    (crc16(&frame[..written - 2]) == crc).then_some(written - 2)
+}
+
+/// A response being built, sent with [`Response::send`].
+struct Response {
+    frame: [u8; FRAME_LEN],
+    len: usize,
+}
+impl Response {
+    fn new(opcode: u8, seq: u8) -> Self {
+        let mut frame = [0; FRAME_LEN];
+        frame[0] = opcode;
+        frame[1] = seq;
+        Self { frame, len: 2 }
+    }
+    fn push(&mut self, bytes: &[u8]) -> PStaticResult<()> {
+        // Room for the CRC
+        let end = self.len + bytes.len();
+        if end + 2 > self.frame.len() {
+            return Err(PErrorVariant::Overflow.into());
+        }
+        self.frame[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+    fn push_address(&mut self, address: Address) -> PStaticResult<()> {
+        self.push(&[address.space.prefix() as u8])?;
+        self.push(&address.addr.to_le_bytes())
+    }
+    fn push_register(&mut self, register: &Register) -> PStaticResult<()> {
+        self.push_address(register.address())?;
+        self.push(&[register.is_wide() as u8])?;
+        self.push(&register.read()?.to_le_bytes())?;
+        self.push(register.name().as_bytes())
+    }
+    /// COBS encode and send the frame.
+    fn send(mut self) {
+        let crc = crc16(&self.frame[..self.len]);
+        self.frame[self.len..self.len + 2].copy_from_slice(&crc.to_le_bytes());
+        self.len += 2;
+        // Frames are shorter than 0xfe bytes, so each block ends in a zero or the end
+        for block in self.frame[..self.len].split(|&byte| byte == 0) {
+            write_byte(block.len() as u8 + 1);
+            block.iter().for_each(|&byte| write_byte(byte));
+        }
+        write_byte(0);
+    }
+}
+
+fn parse_address(payload: &[u8]) -> PStaticResult<Address> {
+    let invalid = || PError::new(PErrorVariant::Overflow, pm_str!("bad address"));
+    let (&prefix, addr) = payload.split_first().ok_or_else(invalid)?;
+    let space = AddressSpace::from_prefix(prefix as char).ok_or_else(invalid)?;
+    let addr = addr.get(..4).ok_or_else(invalid)?;
+    Ok(Address::new(
+        space,
+        u32::from_le_bytes([addr[0], addr[1], addr[2], addr[3]]),
+    ))
+}
+
+/// Handle one request, filling in `response`'s payload.
+fn handle(
+    opcode: u8,
+    payload: &[u8],
+    pos: &mut Option<Address>,
+    response: &mut Response,
+) -> PStaticResult<()> {
+    match opcode {
+        HELLO => response.push(&[VERSION]),
+        READ => {
+            let start = parse_address(payload)?;
+            let len = *payload.get(5).ok_or(PErrorVariant::Overflow)? as usize;
+            if len > MAX_DATA_LEN {
+                return Err(PErrorVariant::Overflow.into());
+            }
+            (0..len).try_for_each(|idx| response.push(&[start.offset(idx as i32).read()?]))
+        }
+        WRITE => {
+            let start = parse_address(payload)?;
+            payload[5..]
+                .iter()
+                .enumerate()
+                .try_for_each(|(idx, &byte)| unsafe { start.offset(idx as i32).write(byte) })
+        }
+        GOTO => {
+            *pos = Some(parse_address(payload)?);
+            Ok(())
+        }
+        MARKER => {
+            let idx = *payload.first().ok_or(PErrorVariant::Overflow)? as usize;
+            let (name, address) = unsafe { MARKERS.get(idx) }.ok_or(PErrorVariant::NotFound)?;
+            response.push(&[unsafe { MARKERS.len() } as u8])?;
+            response.push_address(*address)?;
+            response.push(name.as_bytes())
+        }
+        REGISTER => {
+            let idx = payload.get(..2).ok_or(PErrorVariant::Overflow)?;
+            let idx = u16::from_le_bytes([idx[0], idx[1]]);
+            let mut result = Err(PErrorVariant::NotFound.into());
+            let mut count = 0;
+            registers::for_each(|register| {
+                if count == idx {
+                    result = response.push_register(register);
+                    return true;
+                }
+                count += 1;
+                false
+            });
+            result
+        }
+        STACK_POINTER => match backtrace::panic_stack_pointer() {
+            Some(stack_pointer) => {
+                response.push(&[1])?;
+                response.push(&stack_pointer.to_le_bytes())
+            }
+            None => response.push(&[0]),
+        },
+        // `serve` returns once it is acknowledged
+        EXIT => Ok(()),
+        _ => Err(PError::new(
+            PErrorVariant::NotFound,
+            pm_str!("unknown opcode"),
+        )),
+    }
+}
+
+/// Serve requests until an [`EXIT`] request, with `pos` as the monitor's position.
+pub fn serve(pos: &mut Option<Address>) {
+    let mut frame = [0; FRAME_LEN];
+    loop {
+        let Some(len) = receive(&mut frame) else {
+            let mut response = Response::new(NACK, 0);
+            let _ = response.push(&[PErrorVariant::Checksum.code()]);
+            response.send();
+            continue;
+        };
+        let (opcode, seq) = (frame[0], frame[1]);
+        let mut response = Response::new(opcode, seq);
+        if let Err(error) = handle(opcode, &frame[2..len], pos, &mut response) {
+            response = Response::new(opcode | ERROR, seq);
+            let _ = response.push(&[error.code()]);
+        }
+        response.send();
+        if opcode == EXIT {
+            return;
+        }
+    }
+}
@@ -0,0 +1,286 @@
+//! Named registers of the `atmega2560`, along with their bit fields as named in the datasheet.
+
+use crate::{
+    debug::access::{Address, AddressSpace},
+    types::{
+        error::{PError, PErrorVariant, PStaticResult},
+        string::{pm_str, PStackStr, PmStr},
+    },
+};
+
+use avr_progmem::progmem;
+
+/// Longest line of [`REGISTERS`]
+const LINE_LEN: usize = 72;
+/// Ports and the data address of their `PINx` register, which is followed by `DDRx` and `PORTx`.
+const PORTS: [(char, u16); 11] = [
+    ('A', 0x20),
+    ('B', 0x23),
+    ('C', 0x26),
+    ('D', 0x29),
+    ('E', 0x2c),
+    ('F', 0x2f),
+    ('G', 0x32),
+    ('H', 0x100),
+    ('J', 0x103),
+    ('K', 0x106),
+    ('L', 0x109),
+];
+
+progmem! {
+    /// One register per line: `NAME ADDR(/16) (BIT7 ... BIT0)`, where `ADDR` is the data address in
+    /// hex, `/16` marks a 16-bit register, and `-` is a reserved bit. A `!` after a bit marks a
+    /// flag that writing a 1 clears, so it must not be written back as read. Ports are in
+    /// [`PORTS`]; writing a 1 to a `PINx` bit toggles it, so those bits are all marked.
+    static progmem string REGISTERS = r#"SREG 5f I T H S V N Z C
+SP 5d/16
+RAMPZ 5b
+EIND 5c
+MCUSR 54 - - - JTRF WDRF BORF EXTRF PORF
+MCUCR 55 JTD - - PUD - - IVSEL IVCE
+SMCR 53 - - - - SM2 SM1 SM0 SE
+WDTCSR 60 WDIF! WDIE WDP3 WDCE WDE WDP2 WDP1 WDP0
+CLKPR 61 CLKPCE - - - CLKPS3 CLKPS2 CLKPS1 CLKPS0
+PRR0 64 PRTWI PRTIM2 PRTIM0 - PRTIM1 PRSPI PRUSART0 PRADC
+PRR1 65 - - PRTIM5 PRTIM4 PRTIM3 PRUSART3 PRUSART2 PRUSART1
+GTCCR 43 TSM - - - - - PSRASY PSRSYNC
+EICRA 69 ISC31 ISC30 ISC21 ISC20 ISC11 ISC10 ISC01 ISC00
+EICRB 6a ISC71 ISC70 ISC61 ISC60 ISC51 ISC50 ISC41 ISC40
+EIMSK 3d INT7 INT6 INT5 INT4 INT3 INT2 INT1 INT0
+EIFR 3c INTF7! INTF6! INTF5! INTF4! INTF3! INTF2! INTF1! INTF0!
+PCICR 68 - - - - - PCIE2 PCIE1 PCIE0
+PCIFR 3b - - - - - PCIF2! PCIF1! PCIF0!
+EECR 3f - - EEPM1 EEPM0 EERIE EEMPE EEPE EERE
+EEDR 40
+EEAR 41/16
+SPMCSR 57 SPMIE RWWSB SIGRD RWWSRE BLBSET PGWRT PGERS SPMEN
+TCCR0A 44 COM0A1 COM0A0 COM0B1 COM0B0 - - WGM01 WGM00
+TCCR0B 45 FOC0A FOC0B - - WGM02 CS02 CS01 CS00
+TCNT0 46
+OCR0A 47
+OCR0B 48
+TIMSK0 6e - - - - - OCIE0B OCIE0A TOIE0
+TIFR0 35 - - - - - OCF0B! OCF0A! TOV0!
+TCCR2A b0 COM2A1 COM2A0 COM2B1 COM2B0 - - WGM21 WGM20
+TCCR2B b1 FOC2A FOC2B - - WGM22 CS22 CS21 CS20
+TCNT2 b2
+OCR2A b3
+OCR2B b4
+ASSR b6 - EXCLK AS2 TCN2UB OCR2AUB OCR2BUB TCR2AUB TCR2BUB
+TIMSK2 70 - - - - - OCIE2B OCIE2A TOIE2
+TIFR2 37 - - - - - OCF2B! OCF2A! TOV2!
+TCCR1A 80 COM1A1 COM1A0 COM1B1 COM1B0 COM1C1 COM1C0 WGM11 WGM10
+TCCR1B 81 ICNC1 ICES1 - WGM13 WGM12 CS12 CS11 CS10
+TCCR1C 82 FOC1A FOC1B FOC1C - - - - -
+TCNT1 84/16
+ICR1 86/16
+OCR1A 88/16
+OCR1B 8a/16
+OCR1C 8c/16
+TIMSK1 6f - - ICIE1 - OCIE1C OCIE1B OCIE1A TOIE1
+TIFR1 36 - - ICF1! - OCF1C! OCF1B! OCF1A! TOV1!
+TCCR3A 90 COM3A1 COM3A0 COM3B1 COM3B0 COM3C1 COM3C0 WGM31 WGM30
+TCCR3B 91 ICNC3 ICES3 - WGM33 WGM32 CS32 CS31 CS30
+TCCR3C 92 FOC3A FOC3B FOC3C - - - - -
+TCNT3 94/16
+ICR3 96/16
+OCR3A 98/16
+OCR3B 9a/16
+OCR3C 9c/16
+TIMSK3 71 - - ICIE3 - OCIE3C OCIE3B OCIE3A TOIE3
+TIFR3 38 - - ICF3! - OCF3C! OCF3B! OCF3A! TOV3!
+TCCR4A a0 COM4A1 COM4A0 COM4B1 COM4B0 COM4C1 COM4C0 WGM41 WGM40
+TCCR4B a1 ICNC4 ICES4 - WGM43 WGM42 CS42 CS41 CS40
+TCCR4C a2 FOC4A FOC4B FOC4C - - - - -
+TCNT4 a4/16
+ICR4 a6/16
+OCR4A a8/16
+OCR4B aa/16
+OCR4C ac/16
+TIMSK4 72 - - ICIE4 - OCIE4C OCIE4B OCIE4A TOIE4
+TIFR4 39 - - ICF4! - OCF4C! OCF4B! OCF4A! TOV4!
+TCCR5A 120 COM5A1 COM5A0 COM5B1 COM5B0 COM5C1 COM5C0 WGM51 WGM50
+TCCR5B 121 ICNC5 ICES5 - WGM53 WGM52 CS52 CS51 CS50
+TCCR5C 122 FOC5A FOC5B FOC5C - - - - -
+TCNT5 124/16
+ICR5 126/16
+OCR5A 128/16
+OCR5B 12a/16
+OCR5C 12c/16
+TIMSK5 73 - - ICIE5 - OCIE5C OCIE5B OCIE5A TOIE5
+TIFR5 3a - - ICF5! - OCF5C! OCF5B! OCF5A! TOV5!
+UCSR0A c0 RXC0 TXC0! UDRE0 FE0 DOR0 UPE0 U2X0 MPCM0
+UCSR0B c1 RXCIE0 TXCIE0 UDRIE0 RXEN0 TXEN0 UCSZ02 RXB80 TXB80
+UCSR0C c2 UMSEL01 UMSEL00 UPM01 UPM00 USBS0 UCSZ01 UCSZ00 UCPOL0
+UBRR0 c4/16
+UDR0 c6
+UCSR1A c8 RXC1 TXC1! UDRE1 FE1 DOR1 UPE1 U2X1 MPCM1
+UCSR1B c9 RXCIE1 TXCIE1 UDRIE1 RXEN1 TXEN1 UCSZ12 RXB81 TXB81
+UCSR1C ca UMSEL11 UMSEL10 UPM11 UPM10 USBS1 UCSZ11 UCSZ10 UCPOL1
+UBRR1 cc/16
+UDR1 ce
+UCSR2A d0 RXC2 TXC2! UDRE2 FE2 DOR2 UPE2 U2X2 MPCM2
+UCSR2B d1 RXCIE2 TXCIE2 UDRIE2 RXEN2 TXEN2 UCSZ22 RXB82 TXB82
+UCSR2C d2 UMSEL21 UMSEL20 UPM21 UPM20 USBS2 UCSZ21 UCSZ20 UCPOL2
+UBRR2 d4/16
+UDR2 d6
+UCSR3A 130 RXC3 TXC3! UDRE3 FE3 DOR3 UPE3 U2X3 MPCM3
+UCSR3B 131 RXCIE3 TXCIE3 UDRIE3 RXEN3 TXEN3 UCSZ32 RXB83 TXB83
+UCSR3C 132 UMSEL31 UMSEL30 UPM31 UPM30 USBS3 UCSZ31 UCSZ30 UCPOL3
+UBRR3 134/16
+UDR3 136
+ADC 78/16
+ADCSRA 7a ADEN ADSC ADATE ADIF! ADIE ADPS2 ADPS1 ADPS0
+ADCSRB 7b - ACME - - MUX5 ADTS2 ADTS1 ADTS0
+ADMUX 7c REFS1 REFS0 ADLAR MUX4 MUX3 MUX2 MUX1 MUX0
+DIDR0 7e ADC7D ADC6D ADC5D ADC4D ADC3D ADC2D ADC1D ADC0D
+DIDR2 7d ADC15D ADC14D ADC13D ADC12D ADC11D ADC10D ADC9D ADC8D
+ACSR 50 ACD ACBG ACO ACI! ACIE ACIC ACIS1 ACIS0
+SPCR 4c SPIE SPE DORD MSTR CPOL CPHA SPR1 SPR0
+SPSR 4d SPIF WCOL - - - - - SPI2X
+SPDR 4e
+TWBR b8
+TWSR b9 TWS7 TWS6 TWS5 TWS4 TWS3 - TWPS1 TWPS0
+TWAR ba TWA6 TWA5 TWA4 TWA3 TWA2 TWA1 TWA0 TWGCE
+TWDR bb
+TWCR bc TWINT! TWEA TWSTA TWSTO TWWC TWEN - TWIE"#;
+}
+
+/// A named register; one line of [`REGISTERS`].
+#[derive(Default)]
+pub struct Register {
+    line: PStackStr<LINE_LEN>,
+}
+impl Register {
+    fn field(&self, idx: usize) -> &str {
+        self.line
+            .split_ascii_whitespace()
+            .nth(idx)
+            .unwrap_or_default()
+    }
+    pub fn name(&self) -> &str {
+        self.field(0)
+    }
+    pub fn is_wide(&self) -> bool {
+        self.field(1).ends_with("/16")
+    }
+    pub fn address(&self) -> Address {
+        let addr = self.field(1).trim_end_matches("/16");
+        Address::new(
+            AddressSpace::Data,
+            u32::from_str_radix(addr, 16).unwrap_or_default(),
+        )
+    }
+    /// The named bits as `(bit, name)`, from bit 7 down to bit 0.
+    pub fn bits(&self) -> impl Iterator<Item = (u8, &str)> {
+        self.line
+            .split_ascii_whitespace()
+            .skip(2)
+            .zip((0..8).rev())
+            .filter(|(name, _)| *name != "-")
+            .map(|(name, bit)| (bit, name.trim_end_matches('!')))
+    }
+    /// The bits that act on a 1 being written, i.e. flags cleared or `PINx` bits toggled by it.
+    /// Setting one bit must write 0 to these rather than what they read as.
+    pub fn write_one_bits(&self) -> u16 {
+        self.line
+            .split_ascii_whitespace()
+            .skip(2)
+            .zip((0..8).rev())
+            .filter(|(name, _)| name.ends_with('!'))
+            .fold(0, |mask, (_, bit)| mask | 1 << bit)
+    }
+    /// Find a bit by its name or number.
+    pub fn bit(&self, name: &str) -> Option<u8> {
+        let width = if self.is_wide() { 16 } else { 8 };
+        match name.parse::<u8>() {
+            Ok(bit) => (bit < width).then_some(bit),
+            Err(_) => self
+                .bits()
+                .find(|(_, bit_name)| bit_name.eq_ignore_ascii_case(name))
+                .map(|(bit, _)| bit),
+        }
+    }
+    /// Read the register. 16-bit registers are read low byte first, as the timers require.
+    pub fn read(&self) -> PStaticResult<u16> {
+        let low = self.address().read()?;
+        let high = if self.is_wide() {
+            self.address().offset(1).read()?
+        } else {
+            0
+        };
+        Ok(u16::from_le_bytes([low, high]))
+    }
+    /// Write the register. 16-bit registers are written high byte first, as the timers require.
+    ///
+    /// # Safety
+    /// See [`crate::debug::access::write`].
+    #[require_unsafe_in_body]
+    pub unsafe fn write(&self, value: u16) -> PStaticResult<()> {
+        let [low, high] = value.to_le_bytes();
+        if self.is_wide() {
+            unsafe { self.address().offset(1).write(high)? };
+        } else if high != 0 {
+            return Err(PError::new(
+                PErrorVariant::Overflow,
+                pm_str!("8-bit register"),
+            ));
+        }
+        unsafe { self.address().write(low) }
+    }
+}
+
+/// Call `f` with every register, until it returns `true`.
+pub fn for_each<F>(mut f: F)
+where
+    F: FnMut(&Register) -> bool,
+{
+    let mut register = Register::default();
+    for byte in PmStr::from(&REGISTERS)
+        .bytes()
+        .chain(core::iter::once(b'\n'))
+    {
+        if byte != b'\n' {
+            let _ = register.line.push(byte as char);
+            continue;
+        }
+        if f(&register) {
+            return;
+        }
+        register.line.clear();
+    }
+
+    for (port, base) in PORTS {
+        for (offset, (name, bit_name)) in [("PIN", "PIN"), ("DDR", "DD"), ("PORT", "PORT")]
+            .into_iter()
+            .enumerate()
+        {
+            register.line.clear();
+            let line = &mut register.line;
+            let _ = pasillo_macros::pm_uwrite!(line, "{}{} {:x}", name, port, base + offset as u16);
+            (0..8).rev().for_each(|bit| {
+                let _ = pasillo_macros::pm_uwrite!(line, " {}{}{}", bit_name, port, bit);
+                if name == "PIN" {
+                    let _ = line.push('!');
+                }
+            });
+            if f(&register) {
+                return;
+            }
+        }
+    }
+}
+
+/// Find a register by its name, ignoring case.
+pub fn lookup(name: &str) -> Option<Register> {
+    let mut found = None;
+    for_each(|register| {
+        if register.name().eq_ignore_ascii_case(name) {
+            found = Some(Register {
+                line: register.line.clone(),
+            });
+        }
+        found.is_some()
+    });
+    found
+}
@@ -0,0 +1,132 @@
+//! The kernel's symbol table, embedded in program memory by `build.rs` from the previous build's
+//! ELF. Together with the markers, this names addresses for the hallway monitor.
+
+use crate::{
+    debug::{
+        access::{Address, AddressSpace},
+        memory::MARKERS,
+    },
+    types::string::{PStr, PmStr},
+    utils::write_hex,
+};
+
+include!(concat!(env!("OUT_DIR"), "/symbols.rs"));
+
+/// Longest name in the table; longer ones are cut from the start. Must match `build.rs`.
+pub const MAX_NAME_LEN: usize = 48;
+
+fn read_table(idx: usize) -> u8 {
+    unsafe { avr_progmem::raw::read_byte(TABLE.as_ptr().add(idx)) }
+}
+
+/// A function or static, named by its path without the crate name, e.g. `task::stack::STACK`.
+#[derive(Clone, Copy)]
+pub struct Symbol {
+    pub address: Address,
+    /// Size in bytes, or 0 if unknown
+    pub size: u16,
+    pub name: PmStr,
+}
+impl Symbol {
+    /// Whether `name` is this symbol's name, or the last components of its path, e.g. `STACK` or
+    /// `stack::STACK` for `task::stack::STACK`.
+    pub fn matches(&self, name: &str) -> bool {
+        let mut bytes = self.name.bytes();
+        match self.name.len().checked_sub(name.len()) {
+            Some(0) => {}
+            // A partial path must start after a `::`
+            Some(skip) if skip >= 2 => {
+                if !bytes.by_ref().skip(skip - 2).take(2).eq(*b"::") {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+        !name.is_empty() && bytes.eq(name.bytes())
+    }
+    /// Whether `address` is within this symbol. Symbols of unknown size only contain their start.
+    pub fn contains(&self, address: Address) -> bool {
+        address.space == self.address.space
+            && address.addr >= self.address.addr
+            && address.addr - self.address.addr < (self.size as u32).max(1)
+    }
+}
+
+/// Number of symbols in the table.
+pub fn len() -> usize {
+    LEN
+}
+pub fn is_empty() -> bool {
+    len() == 0
+}
+pub fn get(idx: usize) -> Option<Symbol> {
+    if idx >= len() {
+        return None;
+    }
+    let entry = |offset: usize| read_table(idx * ENTRY_LEN + offset);
+    let space = AddressSpace::from_prefix(entry(3) as char)?;
+    let name_start = u16::from_le_bytes([entry(6), entry(7)]) as usize;
+    // SAFETY: `build.rs` only writes UTF-8 names, within `NAMES`
+    let name = unsafe { PmStr::from_raw_parts(NAMES.as_ptr().add(name_start), entry(8) as usize) };
+    Some(Symbol {
+        address: Address::new(space, u32::from_le_bytes([entry(0), entry(1), entry(2), 0])),
+        size: u16::from_le_bytes([entry(4), entry(5)]),
+        name,
+    })
+}
+/// All symbols, sorted by address space and then address.
+pub fn iter() -> impl Iterator<Item = Symbol> {
+    (0..len()).filter_map(get)
+}
+
+/// Find a symbol by name, preferring a full match; see [`Symbol::matches`].
+pub fn lookup(name: &str) -> Option<Symbol> {
+    iter()
+        .find(|symbol| symbol.name.len() == name.len() && symbol.matches(name))
+        .or_else(|| iter().find(|symbol| symbol.matches(name)))
+}
+
+/// The address of the marker or symbol called `name`. Markers take precedence.
+pub fn address_of(name: &str) -> Option<Address> {
+    unsafe { MARKERS.iter() }
+        .find(|(marker, _)| *marker == name)
+        .map(|(_, address)| *address)
+        .or_else(|| lookup(name).map(|symbol| symbol.address))
+}
+
+/// Name `address` as a name and an offset into it: a marker at exactly `address`, or else the
+/// innermost symbol containing it.
+pub fn resolve(address: Address) -> Option<(PStr<'static>, u32)> {
+    if let Some((name, _)) = unsafe { MARKERS.iter() }.find(|(_, marker)| *marker == address) {
+        return Some((PStr::Fixed(*name), 0));
+    }
+    iter()
+        .filter(|symbol| symbol.contains(address))
+        .last()
+        .map(|symbol| {
+            (
+                PStr::Progmem(symbol.name),
+                address.addr - symbol.address.addr,
+            )
+        })
+}
+
+/// Displays what an address resolves to as ` <name+0xoffset>`, or nothing if it doesn't.
+pub struct Annotation(pub Address);
+impl ufmt::uDisplay for Annotation {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized,
+    {
+        let Some((name, offset)) = resolve(self.0) else {
+            return Ok(());
+        };
+        f.write_str(" <")?;
+        ufmt::uDisplay::fmt(&name, f)?;
+        if offset != 0 {
+            f.write_str("+0x")?;
+            write_hex(f, offset, 1)?;
+        }
+        f.write_char('>')
+    }
+}
@@ -0,0 +1,170 @@
+//! Data watchpoints: snapshots of RAM ranges, compared on every kernel tick.
+//!
+//! There is no hardware support, so a change is only noticed on the tick after it happens, and
+//! the task reported is the one running at that tick rather than necessarily the one that wrote.
+//! The kernel stops in the tick that noticed it, so a backtrace starts in the tick's interrupt
+//! handler, and the registers saved for GDB are the handler's.
+
+use crate::{
+    debug::{
+        access::{Address, AddressSpace, DATA_SIZE},
+        console::{debug_println, helper_print},
+    },
+    task::{scheduler::TASKS, state::TaskState},
+    types::{
+        array::PStackArr,
+        error::{PError, PErrorVariant, PStaticResult},
+        string::pm_str,
+    },
+};
+
+use avr_device::interrupt::{self, Mutex};
+use core::{
+    cell::{Cell, RefCell},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+pub const NUM_WATCHPOINTS: usize = 4;
+/// Longest range a watchpoint can cover, in bytes.
+pub const MAX_WATCH_LEN: usize = 16;
+
+pub struct Watchpoint {
+    pub start: Address,
+    pub len: u8,
+    snapshot: [u8; MAX_WATCH_LEN],
+}
+impl Watchpoint {
+    /// Read RAM directly rather than through [`Address::read`], as this runs in the tick.
+    fn read(&self, idx: usize) -> u8 {
+        unsafe { ((self.start.addr as usize + idx) as *const u8).read_volatile() }
+    }
+    /// The first byte that differs from the snapshot.
+    fn changed(&self) -> Option<usize> {
+        (0..self.len as usize).find(|&idx| self.read(idx) != self.snapshot[idx])
+    }
+    fn snapshot(&mut self) {
+        (0..self.len as usize).for_each(|idx| self.snapshot[idx] = self.read(idx));
+    }
+}
+
+static WATCHPOINTS: Mutex<RefCell<PStackArr<Watchpoint, NUM_WATCHPOINTS>>> =
+    Mutex::new(RefCell::new(PStackArr::new()));
+
+/// A change found by [`check`], until [`report`] prints it.
+struct Hit {
+    idx: u8,
+    /// The first byte that changed
+    address: Address,
+    /// The task running at the tick it was found
+    task_id: Option<u8>,
+    len: u8,
+    old: [u8; MAX_WATCH_LEN],
+    new: [u8; MAX_WATCH_LEN],
+}
+static HIT: Mutex<Cell<Option<Hit>>> = Mutex::new(Cell::new(None));
+/// Whether there is a [`HIT`], so that the tick can check without a critical section.
+static PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Watch the `len` bytes of RAM from `start`, as they are now.
+pub fn add(start: Address, len: usize) -> PStaticResult<()> {
+    if start.space != AddressSpace::Data {
+        return Err(PError::new(
+            PErrorVariant::Permission,
+            pm_str!("only RAM can be watched"),
+        ));
+    }
+    if len == 0 || len > MAX_WATCH_LEN || start.addr + len as u32 > DATA_SIZE {
+        return Err(PError::new(PErrorVariant::Overflow, pm_str!("bad length")));
+    }
+    let mut watchpoint = Watchpoint {
+        start,
+        len: len as u8,
+        snapshot: [0; MAX_WATCH_LEN],
+    };
+    watchpoint.snapshot();
+    interrupt::free(|cs| WATCHPOINTS.borrow(cs).borrow_mut().push(watchpoint))
+        .map_err(|_| PError::new(PErrorVariant::Overflow, pm_str!("watchpoints full")))
+}
+/// Stop watching the watchpoint numbered `idx` by [`for_each`].
+pub fn remove(idx: usize) -> PStaticResult<()> {
+    interrupt::free(|cs| WATCHPOINTS.borrow(cs).borrow_mut().remove(idx))
+        .map(|_| ())
+        .ok_or(PErrorVariant::NotFound.into())
+}
+/// Each watchpoint, numbered.
+pub fn for_each(mut f: impl FnMut(usize, &Watchpoint)) {
+    interrupt::free(|cs| {
+        WATCHPOINTS
+            .borrow(cs)
+            .borrow()
+            .iter()
+            .enumerate()
+            .for_each(|(idx, watchpoint)| f(idx, watchpoint))
+    })
+}
+
+/// Compare every watchpoint to its snapshot, and keep the first change for [`report`]. Called
+/// from the kernel tick, which then stops if there is one.
+pub fn check() {
+    if PENDING.load(Ordering::Relaxed) {
+        return;
+    }
+    interrupt::free(|cs| {
+        let mut watchpoints = WATCHPOINTS.borrow(cs).borrow_mut();
+        let changed = watchpoints
+            .iter()
+            .enumerate()
+            .find_map(|(idx, watchpoint)| Some((idx, watchpoint.changed()?)));
+        let Some((idx, first)) = changed else {
+            return;
+        };
+        let Some(watchpoint) = watchpoints.get_mut(idx) else {
+            return;
+        };
+        let mut hit = Hit {
+            idx: idx as u8,
+            address: watchpoint.start.offset(first as i32),
+            task_id: unsafe { TASKS.iter() }
+                .find(|task| task.state == TaskState::Running)
+                .map(|task| task.id),
+            len: watchpoint.len,
+            old: watchpoint.snapshot,
+            new: [0; MAX_WATCH_LEN],
+        };
+        // Continuing waits for the next change
+        watchpoint.snapshot();
+        hit.new = watchpoint.snapshot;
+        HIT.borrow(cs).set(Some(hit));
+        PENDING.store(true, Ordering::Relaxed);
+    })
+}
+/// Whether [`check`] found a change that hasn't been reported yet.
+#[inline(always)]
+pub fn pending() -> bool {
+    PENDING.load(Ordering::Relaxed)
+}
+/// Print the change found by [`check`], if any, and let it look for the next one.
+pub fn report() {
+    let Some(hit) = interrupt::free(|cs| HIT.borrow(cs).take()) else {
+        return;
+    };
+    PENDING.store(false, Ordering::Relaxed);
+    match hit.task_id {
+        Some(task_id) => debug_println!(
+            "WATCHPOINT {} {} changed in task #{}",
+            hit.idx,
+            hit.address,
+            task_id
+        ),
+        None => debug_println!("WATCHPOINT {} {} changed", hit.idx, hit.address),
+    }
+    helper_print!("", "", "old");
+    hit.old[..hit.len as usize]
+        .iter()
+        .for_each(|byte| helper_print!(" ", "", "{:02x}", *byte));
+    helper_print!("", "\nnew", "");
+    hit.new[..hit.len as usize]
+        .iter()
+        .for_each(|byte| helper_print!(" ", "", "{:02x}", *byte));
+    helper_print!("", "\n", "");
+}
@@ -11,11 +11,14 @@
 #![feature(const_trait_impl)]
 #![feature(effects)]
 #![feature(asm_experimental_arch)]
+#![feature(abi_avr_interrupt)]
 
+pub mod crash;
 pub mod debug;
 pub mod driver;
 pub mod module;
 pub mod panic;
+pub mod reset;
 pub mod shared;
 pub mod task;
 pub mod types;
@@ -23,29 +26,59 @@ pub mod utils;
 
 use core::{alloc::Layout, hint::black_box};
 
-use arduino_hal::default_serial;
-use debug::{console::debug_println, memory::add_marker};
-use task::stack::{jump_to_stack, STACK, STACK_LEN};
+use arduino_hal::{default_serial, hal::wdt::Wdt};
+use debug::{breakpoint::breakpoint, console::debug_println, memory::add_marker};
+use task::{
+    scheduler::{Task, TASKS},
+    stack::{jump_to_stack, STACK, STACK_LEN},
+    state::TaskState,
+};
 
 #[macro_use]
 extern crate require_unsafe_in_body;
 
 #[arduino_hal::entry]
 fn main() -> ! {
-    unsafe { debug::memory::add_marker_manual("main", __avr_device_rt_main as *const u8) };
+    unsafe {
+        debug::memory::add_marker_manual(
+            "main",
+            debug::access::Address::function(__avr_device_rt_main as *const u8),
+        )
+    };
 
     add_marker!("stack", STACK);
     unsafe {
         jump_to_stack(core::ptr::addr_of!(STACK[0]).add(STACK_LEN) as *const _);
     }
+    // The kernel itself is the first task
+    unsafe {
+        let stack = core::ptr::addr_of!(STACK[0]) as *const u8;
+        let mut kernel = Task::new(0, 0, stack, stack.add(STACK_LEN));
+        kernel.state = TaskState::Running;
+        let _ = TASKS.push(kernel);
+    }
 
     let peripherals = unsafe { arduino_hal::Peripherals::steal() };
     let pins = arduino_hal::pins!(peripherals);
+    // Before the watchdog's flag is cleared
+    reset::capture(&peripherals.CPU);
+    panic::capture_reboot();
+    // A watchdog reset, e.g. from the panic policy, leaves the watchdog running
+    Wdt::new(peripherals.WDT, &peripherals.CPU.mcusr).stop();
     let serial = default_serial!(peripherals, pins, shared::BAUD_RATE);
     debug::console::set_console(serial);
+    task::tick::start(peripherals.TC0);
+    unsafe { avr_device::interrupt::enable() };
+    // First, so that `pasillo-ctl log` can check its ELF before decoding anything
+    #[cfg(feature = "binary-log")]
+    debug::log::write_image_record();
+    reset::report();
+    crash::report_previous();
+    add_marker!("crash", crash::PREVIOUS);
 
     let x = [b'G'; 128];
     add_marker!("x", x);
+    breakpoint!(0);
 
-    panic!("")
+    panic!("end of main")
 }
@@ -1,45 +1,332 @@
 //! Panic handler!
+//!
+//! What happens after a panic is reported is up to the [`PanicPolicy`]: [`PANIC_POLICY`] as built,
+//! unless overridden in EEPROM with [`set_policy`].
+//!
+//! Formatted messages are only rendered with the `console` feature, to leave `core::fmt` out of
+//! builds that can't print them; panic with [`fail`] instead to report a [`PError`] either way.
+//!
+//! [`PanicPolicy::Blink`] repeats three numbers on D13: the error's [`PErrorVariant`] code (0 for
+//! plain panics), then the panic site as its file's [`file_id`] and its line. A line alone could be
+//! in any file, so the file ID is also printed with the panic and in the crash record, to look it
+//! up. Each decimal digit is that many short blinks, and 0 is one long blink. Digits are 1s apart,
+//! the numbers 2s apart, and the code repeats after 4s. e.g. `Overflow` (9) at line 40 of a file
+//! with ID 3: 9 short, 2s, 3 short, 2s, 4 short, 1s, 1 long, 4s.
 
-#[cfg(debug_assertions)]
+#[cfg(feature = "monitor")]
 use crate::debug::hallway::HallwayMonitor;
 use crate::{
-    debug::console::debug_println,
-    shared::{UsbSerial, BAUD_RATE},
+    crash,
+    debug::{
+        access::{read_eeprom, write_eeprom},
+        console::{debug_print, debug_println, helper_print, write_byte},
+    },
+    shared::{UsbSerial, BAUD_RATE, EEPROM_PANIC_POLICY, MAX_PANIC_REBOOTS, PANIC_POLICY},
+    task::{
+        stack::stack_pointer,
+        tick::{self, TICK_HZ},
+    },
+    types::error::{PError, PErrorVariant, STATIC_CONTEXT_LEN},
+    utils::{crc16, BoundedWriter},
 };
 
-use arduino_hal::{default_serial, delay_ms};
+use arduino_hal::{
+    default_serial, delay_ms,
+    hal::{
+        port::PB7,
+        wdt::{Timeout, Wdt},
+    },
+    port::{mode::Output, Pin},
+};
 use core::panic::PanicInfo;
 
-/// Panic and run [`HallwayMonitor`].
+/// Running this long before panicking resets the count of reboots in a row.
+const STABLE_TICKS: u32 = 60 * TICK_HZ;
+/// Erased EEPROM, so the policy as built
+const NO_POLICY: u8 = 0xff;
+/// Longest panic message printed
+const MESSAGE_LEN: usize = 128;
+
+// Blink code timings, in milliseconds
+const SHORT_BLINK: u16 = 200;
+const LONG_BLINK: u16 = 800;
+const BLINK_GAP: u16 = 300;
+const DIGIT_GAP: u16 = 1000;
+const NUMBER_GAP: u16 = 2000;
+const REPEAT_GAP: u16 = 4000;
+
+/// D13, the on-board LED
+type Led = Pin<Output, PB7>;
+
+/// The error given to [`fail`].
+static mut PANIC_ERROR: Option<PError<STATIC_CONTEXT_LEN>> = None;
+
+/// Panic with `error`, which is reported in place of a message.
+#[track_caller]
+pub fn fail(error: impl Into<PError<STATIC_CONTEXT_LEN>>) -> ! {
+    unsafe { PANIC_ERROR = Some(error.into()) };
+    panic!()
+}
+pub fn panic_error() -> Option<&'static PError<STATIC_CONTEXT_LEN>> {
+    unsafe { PANIC_ERROR.as_ref() }
+}
+
+/// Render the panic's message through `writer`: the error given to [`fail`], or else the message,
+/// formatted only with the `console` feature.
+pub fn write_message<F: FnMut(u8)>(info: &PanicInfo, writer: &mut BoundedWriter<F>) {
+    if let Some(error) = panic_error() {
+        let _ = ufmt::uwrite!(writer, "{}", error);
+        return;
+    }
+    let Some(message) = info.message() else {
+        return;
+    };
+    #[cfg(feature = "console")]
+    let _ = core::fmt::Write::write_fmt(writer, *message);
+    #[cfg(not(feature = "console"))]
+    let _ = ufmt::uWrite::write_str(writer, message.as_str().unwrap_or("<formatted message>"));
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// Run the hallway monitor, or halt without the `monitor` feature as there is none
+    Monitor,
+    /// Stop with interrupts disabled, until reset
+    Halt,
+    /// Blink the error code and panic site on D13 forever; see the module docs
+    Blink,
+    /// Reset with the watchdog after this many seconds. After [`MAX_PANIC_REBOOTS`] in a row
+    /// that each panicked within a minute of rebooting, halt instead.
+    Reboot(u8),
+}
+impl PanicPolicy {
+    /// `code, seconds` as kept in EEPROM
+    const fn encode(self) -> [u8; 2] {
+        match self {
+            Self::Monitor => [0, 0],
+            Self::Halt => [1, 0],
+            Self::Blink => [2, 0],
+            Self::Reboot(seconds) => [3, seconds],
+        }
+    }
+    const fn decode(bytes: [u8; 2]) -> Option<Self> {
+        match bytes {
+            [0, _] => Some(Self::Monitor),
+            [1, _] => Some(Self::Halt),
+            [2, _] => Some(Self::Blink),
+            [3, seconds] => Some(Self::Reboot(seconds)),
+            _ => None,
+        }
+    }
+}
+
+impl ufmt::uDisplay for PanicPolicy {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized,
+    {
+        match self {
+            Self::Monitor => f.write_str("monitor"),
+            Self::Halt => f.write_str("halt"),
+            Self::Blink => f.write_str("blink"),
+            Self::Reboot(seconds) => ufmt::uwrite!(f, "reboot {}", seconds),
+        }
+    }
+}
+
+/// The policy in EEPROM, or [`PANIC_POLICY`] if there is none.
+pub fn policy() -> PanicPolicy {
+    PanicPolicy::decode([
+        read_eeprom(EEPROM_PANIC_POLICY),
+        read_eeprom(EEPROM_PANIC_POLICY + 1),
+    ])
+    .unwrap_or(PANIC_POLICY)
+}
+/// Override the policy as built, or go back to it with `None`.
+pub fn set_policy(policy: Option<PanicPolicy>) {
+    let bytes = policy.map_or([NO_POLICY; 2], PanicPolicy::encode);
+    write_eeprom(EEPROM_PANIC_POLICY, bytes[0]);
+    write_eeprom(EEPROM_PANIC_POLICY + 1, bytes[1]);
+}
+
+/// Reboots in a row, and its complement as a check. Kept across resets, and only trusted after a
+/// panic reboot.
+#[link_section = ".noinit"]
+static mut PANIC_REBOOTS: [u8; 2] = [0; 2];
+/// What [`REBOOTING`] holds while the watchdog is about to reset for [`PanicPolicy::Reboot`].
+const REBOOT_MAGIC: [u8; 4] = *b"rbt!";
+/// Set just before a panic reboot, so that the next boot can tell it was one. `MCUSR` can't, as
+/// the bootloader may clear it; see [`crate::reset`].
+#[link_section = ".noinit"]
+static mut REBOOTING: [u8; 4] = [0; 4];
+/// Whether the kernel started from a panic reboot; see [`capture_reboot`].
+static mut REBOOTED: bool = false;
+
+/// Note whether the kernel started from a panic reboot, and forget it for the next reset. Must run
+/// at boot, before anything can panic.
+pub fn capture_reboot() {
+    unsafe {
+        REBOOTED = REBOOTING == REBOOT_MAGIC;
+        REBOOTING = [0; 4];
+    }
+}
+
+fn panic_reboots() -> u8 {
+    let [count, check] = unsafe { PANIC_REBOOTS };
+    if check == !count {
+        count
+    } else {
+        0
+    }
+}
+
+fn halt() -> ! {
+    debug_println!("Halted.");
+    avr_device::interrupt::disable();
+    loop {}
+}
+
+fn flash(led: &mut Led, on: u16) {
+    led.set_high();
+    delay_ms(on);
+    led.set_low();
+    delay_ms(BLINK_GAP);
+}
+/// Blink `number` in decimal, most significant digit first.
+fn blink_number(led: &mut Led, number: u16) {
+    let mut divisor = 1;
+    while number / divisor >= 10 {
+        divisor *= 10;
+    }
+    loop {
+        match number / divisor % 10 {
+            0 => flash(led, LONG_BLINK),
+            digit => (0..digit).for_each(|_| flash(led, SHORT_BLINK)),
+        }
+        if divisor == 1 {
+            return;
+        }
+        divisor /= 10;
+        delay_ms(DIGIT_GAP);
+    }
+}
+fn blink_code(led: &mut Led, code: u8, file_id: u8, line: u16) -> ! {
+    loop {
+        blink_number(led, code as u16);
+        delay_ms(NUMBER_GAP);
+        blink_number(led, file_id as u16);
+        delay_ms(NUMBER_GAP);
+        blink_number(led, line);
+        delay_ms(REPEAT_GAP);
+    }
+}
+
+/// The ID a panic site's file blinks as, from 0 to 99: a hash of its name without the directories,
+/// so that a crash record's shortened path gives the same ID.
+pub fn file_id(file: &str) -> u8 {
+    let name = file
+        .rsplit(|char| char == '/' || char == '\\')
+        .next()
+        .unwrap_or(file);
+    (crc16(name.as_bytes()) % 100) as u8
+}
+
+/// Panic, then follow the [`PanicPolicy`].
 #[panic_handler]
 pub fn panic(info: &PanicInfo) -> ! {
+    // Before anything else is pushed or changed, for `bt` and GDB
+    #[cfg(feature = "monitor")]
+    {
+        crate::debug::backtrace::save_registers();
+        crate::debug::backtrace::capture();
+    }
+    let (stack_pointer, sreg) = (stack_pointer(), crash::sreg());
+    // Nothing else should run, e.g. the tick stopping at a watchpoint
+    avr_device::interrupt::disable();
+    crash::save(info, stack_pointer, sreg);
+
     // Avoid race condition with the serial handle
     let peripherals = unsafe { arduino_hal::Peripherals::steal() };
     let pins = arduino_hal::pins!(peripherals);
     let serial: UsbSerial = default_serial!(peripherals, pins, BAUD_RATE);
     crate::debug::console::set_console(serial);
+    // What was logged before the panic, even if it panicked while the monitor owned the console
+    crate::debug::log::resume(false);
+    crate::debug::log::flush();
 
-    // Print out panic location
-    if let Some(message) = info.message() {
-        debug_println!("PANICKED! {}", message.as_str().unwrap_or_default());
-    }
+    // Print out panic message and location
+    debug_print!("PANICKED! ");
+    write_message(info, &mut BoundedWriter::new(MESSAGE_LEN, write_byte));
+    helper_print!("", '\n', "");
     if let Some(loc) = info.location() {
-        debug_println!("PANICKED! {}:{}:{}", loc.file(), loc.line(), loc.column());
+        debug_println!(
+            "PANICKED! {}:{}:{} (file ID {})",
+            loc.file(),
+            loc.line(),
+            loc.column(),
+            file_id(loc.file())
+        );
     }
 
-    #[cfg(debug_assertions)]
-    unsafe {
-        debug_println!("Enter to start Hallway Monitor...");
-        crate::debug::console::read_line::<1>();
-        HallwayMonitor::new().interactive()
-    };
-    #[cfg(not(debug_assertions))]
-    debug_println!("Run with debug assertions to start Hallway Monitor.");
+    // Still attached from continuing at a breakpoint, so GDB is waiting for the kernel to stop
+    #[cfg(feature = "monitor")]
+    if crate::debug::gdb::is_attached() {
+        crate::debug::gdb::serve();
+    }
 
-    debug_println!("Entering busy loop.");
     let mut led = pins.d13.into_output();
-    loop {
-        led.toggle();
-        delay_ms(500);
+    match policy() {
+        #[cfg(feature = "monitor")]
+        PanicPolicy::Monitor => unsafe {
+            debug_println!("Enter to start Hallway Monitor...");
+            crate::debug::console::read_line::<1>();
+            HallwayMonitor::new().interactive()
+        },
+        #[cfg(not(feature = "monitor"))]
+        PanicPolicy::Monitor => {
+            debug_println!("Build with the `monitor` feature to start Hallway Monitor.");
+            halt()
+        }
+        PanicPolicy::Halt => halt(),
+        PanicPolicy::Blink => {}
+        PanicPolicy::Reboot(seconds) => {
+            let quick = unsafe { REBOOTED } && tick::ticks() < STABLE_TICKS;
+            let reboots = if quick {
+                panic_reboots().saturating_add(1)
+            } else {
+                1
+            };
+            if reboots > MAX_PANIC_REBOOTS {
+                debug_println!("Panicked after {} reboots in a row.", MAX_PANIC_REBOOTS);
+                halt()
+            }
+            unsafe { PANIC_REBOOTS = [reboots, !reboots] };
+            debug_println!("Rebooting in {}s.", seconds);
+            for _ in 0..seconds as u16 * 2 {
+                led.toggle();
+                delay_ms(500);
+            }
+            let mut watchdog = Wdt::new(peripherals.WDT, &peripherals.CPU.mcusr);
+            unsafe { REBOOTING = REBOOT_MAGIC };
+            let _ = watchdog.start(Timeout::Ms16);
+            loop {}
+        }
     }
+
+    let code = panic_error().map_or(PErrorVariant::Unknown, |error| error.variant);
+    let (file_id, line) = info.location().map_or((0, 0), |location| {
+        (file_id(location.file()), location.line())
+    });
+    debug_println!(
+        "Blinking code {} for file ID {} line {}.",
+        code.code(),
+        file_id,
+        line
+    );
+    blink_code(
+        &mut led,
+        code.code(),
+        file_id,
+        line.min(u16::MAX as u32) as u16,
+    )
 }
@@ -0,0 +1,133 @@
+//! Why the kernel started, from the reset flags in `MCUSR`, and how often each cause happened.
+//!
+//! [`capture`] must run before anything clears `MCUSR`, e.g. setting up the watchdog clears
+//! `WDRF`. The counts are kept in EEPROM at [`EEPROM_RESET_COUNTS`], a `u16` per cause.
+//!
+//! The Mega2560's stock stk500v2 bootloader clears `MCUSR` before starting the kernel, so with it
+//! nearly every reset reads as [`ResetCause::Unknown`]. The causes are only meaningful without a
+//! bootloader (flashed over ISP), or with one that leaves `MCUSR` alone. Nothing relies on them:
+//! panic reboots are recognized on their own; see [`crate::panic::capture_reboot`].
+
+use crate::{
+    debug::{
+        access::{read_eeprom, write_eeprom},
+        log,
+    },
+    shared::EEPROM_RESET_COUNTS,
+    types::string::{pm_str, PmStr},
+};
+
+// `MCUSR` bits
+const PORF: u8 = 1 << 0;
+const EXTRF: u8 = 1 << 1;
+const BORF: u8 = 1 << 2;
+const WDRF: u8 = 1 << 3;
+const JTRF: u8 = 1 << 4;
+/// Erased EEPROM
+const NO_COUNT: u16 = 0xffff;
+
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ResetCause {
+    PowerOn = 0,
+    /// The reset pin, e.g. the reset button or the USB serial's DTR when uploading
+    External = 1,
+    BrownOut = 2,
+    Watchdog = 3,
+    Jtag = 4,
+    /// No flags set, e.g. a jump to 0 or a bootloader that cleared them
+    Unknown = 5,
+}
+impl ResetCause {
+    pub const ALL: [Self; 6] = [
+        Self::PowerOn,
+        Self::External,
+        Self::BrownOut,
+        Self::Watchdog,
+        Self::Jtag,
+        Self::Unknown,
+    ];
+
+    /// Several flags can be set at once, e.g. a brown-out while powering on; the earliest cause in
+    /// the power on, brown-out, watchdog, external, JTAG order wins.
+    pub fn from_flags(flags: u8) -> Self {
+        [
+            (PORF, Self::PowerOn),
+            (BORF, Self::BrownOut),
+            (WDRF, Self::Watchdog),
+            (EXTRF, Self::External),
+            (JTRF, Self::Jtag),
+        ]
+        .iter()
+        .find(|(bit, _)| flags & bit != 0)
+        .map_or(Self::Unknown, |(_, cause)| *cause)
+    }
+    pub fn name(self) -> PmStr {
+        match self {
+            Self::PowerOn => pm_str!("power on"),
+            Self::External => pm_str!("external reset"),
+            Self::BrownOut => pm_str!("brown-out"),
+            Self::Watchdog => pm_str!("watchdog"),
+            Self::Jtag => pm_str!("JTAG"),
+            Self::Unknown => pm_str!("unknown"),
+        }
+    }
+    fn count_addr(self) -> u16 {
+        EEPROM_RESET_COUNTS + self as u16 * 2
+    }
+    /// How many times the kernel has started from this cause.
+    pub fn count(self) -> u16 {
+        let count = u16::from_le_bytes([
+            read_eeprom(self.count_addr()),
+            read_eeprom(self.count_addr() + 1),
+        ]);
+        if count == NO_COUNT {
+            0
+        } else {
+            count
+        }
+    }
+}
+impl ufmt::uDisplay for ResetCause {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized,
+    {
+        ufmt::uDisplay::fmt(&self.name(), f)
+    }
+}
+
+/// `MCUSR` as it was at reset.
+static mut FLAGS: u8 = 0;
+
+/// Read and clear `MCUSR`, so that the next reset's flags aren't mixed with these.
+pub fn capture(cpu: &arduino_hal::pac::CPU) {
+    let flags = cpu.mcusr.read().bits();
+    cpu.mcusr.reset();
+    unsafe { FLAGS = flags };
+}
+/// `MCUSR` as it was at reset.
+pub fn flags() -> u8 {
+    unsafe { FLAGS }
+}
+pub fn cause() -> ResetCause {
+    ResetCause::from_flags(flags())
+}
+
+/// Count this reset in EEPROM and log it.
+pub fn report() {
+    let cause = cause();
+    // Short of `NO_COUNT`, so that it doesn't read as erased
+    let count = (cause.count() + 1).min(NO_COUNT - 1);
+    count
+        .to_le_bytes()
+        .iter()
+        .enumerate()
+        .for_each(|(idx, byte)| write_eeprom(cause.count_addr() + idx as u16, *byte));
+    log::info!(
+        "Reset by {} (MCUSR 0x{:02x}), {} times so far",
+        cause.name(),
+        flags(),
+        count
+    );
+}
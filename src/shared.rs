@@ -1,7 +1,30 @@
-pub const DEBUG: bool = true;
-pub const TRACE: bool = true;
+use crate::{debug::log::Level, panic::PanicPolicy};
+
 pub const BAUD_RATE: u32 = 57_600;
 pub const MAX_DELTATIME: u32 = 10_000;
+/// Most verbose level logged, as built; see [`crate::debug::log`]
+pub const LOG_LEVEL: Level = if cfg!(feature = "trace") {
+    Level::Trace
+} else {
+    Level::Debug
+};
+/// Modules logged at another level than [`LOG_LEVEL`], e.g. `("task::tick", Level::Warn)`
+pub const LOG_FILTERS: &[(&str, Level)] = &[];
+/// What to do after a panic, unless overridden at [`EEPROM_PANIC_POLICY`]
+pub const PANIC_POLICY: PanicPolicy = if cfg!(feature = "monitor") {
+    PanicPolicy::Monitor
+} else {
+    PanicPolicy::Blink
+};
+/// Reboots in a row before [`PanicPolicy::Reboot`] halts instead
+pub const MAX_PANIC_REBOOTS: u8 = 3;
+
+/// A `u16` per [`crate::reset::ResetCause`], counting resets by cause
+pub const EEPROM_RESET_COUNTS: u16 = 0xf72;
+/// 2 bytes overriding [`PANIC_POLICY`]; see [`crate::panic::set_policy`].
+pub const EEPROM_PANIC_POLICY: u16 = 0xf7e;
+/// The last 128 bytes of EEPROM hold the last crash; see [`crate::crash`].
+pub const EEPROM_CRASH: u16 = 0xf80;
 
 pub type UsbSerial = arduino_hal::Usart<
     arduino_hal::pac::USART0,
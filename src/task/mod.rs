@@ -3,3 +3,4 @@
 pub mod scheduler;
 pub mod stack;
 pub mod state;
+pub mod tick;
@@ -1,3 +1,16 @@
+use crate::{
+    task::{
+        stack::{NUM_STACKS, STACK_FILL},
+        state::TaskState,
+    },
+    types::array::PStackArr,
+};
+
+/// At most one task per stack.
+pub const MAX_TASKS: usize = NUM_STACKS;
+/// Every task, running or not.
+pub static mut TASKS: PStackArr<Task, MAX_TASKS> = PStackArr::new();
+
 #[repr(C)]
 pub struct Task {
     /// Pointer to the highest stack element
@@ -6,4 +19,36 @@ pub struct Task {
     pub stack_end: *const u8,
     /// Start run time in micros
     pub start_runtime: u32,
+    pub id: u8,
+    pub state: TaskState,
+    /// Higher runs first
+    pub priority: u8,
+    /// Total run time in micros, counted by the kernel tick a whole tick at a time
+    pub cpu_time: u32,
+}
+impl Task {
+    /// A task that is yet to run on the stack from `stack_start` to `stack_end`.
+    pub const fn new(id: u8, priority: u8, stack_start: *const u8, stack_end: *const u8) -> Self {
+        Self {
+            stack_top: stack_end,
+            stack_start,
+            stack_end,
+            start_runtime: 0,
+            id,
+            state: TaskState::Ready,
+            priority,
+            cpu_time: 0,
+        }
+    }
+    pub fn stack_len(&self) -> usize {
+        self.stack_end.addr() - self.stack_start.addr()
+    }
+    /// The most stack this task has used so far, going by how much of the stack's fill pattern has
+    /// been overwritten.
+    pub fn high_water_mark(&self) -> usize {
+        let untouched = (0..self.stack_len())
+            .take_while(|&idx| unsafe { self.stack_start.add(idx).read_volatile() } == STACK_FILL)
+            .count();
+        self.stack_len() - untouched
+    }
 }
@@ -3,8 +3,11 @@
 use core::arch::asm;
 
 pub const STACK_LEN: usize = 512;
+pub const NUM_STACKS: usize = 1;
+/// What unused stack is filled with, to measure how much of it has been used.
+pub const STACK_FILL: u8 = 0xff;
 #[link_name = "stack"]
-pub static mut STACK: [[u8; STACK_LEN]; 1] = [[0xff; STACK_LEN]; 1];
+pub static mut STACK: [[u8; STACK_LEN]; NUM_STACKS] = [[STACK_FILL; STACK_LEN]; NUM_STACKS];
 
 pub unsafe fn jump_to_stack(location: *const u8) {
     let addr = location.addr() as u32;
@@ -14,3 +17,18 @@ pub unsafe fn jump_to_stack(location: *const u8) {
 
     asm!("out __SP_L__, {low}", "out __SP_H__, {high}", low = in(reg) stack_pointer_low, high = in(reg) stack_pointer_high);
 }
+
+#[inline(always)]
+pub fn stack_pointer() -> u16 {
+    let (low, high): (u8, u8);
+    unsafe {
+        asm!(
+            "in {low}, __SP_L__",
+            "in {high}, __SP_H__",
+            low = out(reg) low,
+            high = out(reg) high,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+    u16::from_le_bytes([low, high])
+}
@@ -1 +1,35 @@
+//! Task states.
 
+use crate::types::string::{pm_str, PmStr};
+
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    /// Waiting to be scheduled
+    Ready = 0,
+    Running = 1,
+    /// Waiting on a resource, e.g. a lock or I/O
+    Blocked = 2,
+    Sleeping = 3,
+    /// Done, but not yet removed from the task table
+    Exited = 4,
+}
+impl TaskState {
+    pub fn name(self) -> PmStr {
+        match self {
+            Self::Ready => pm_str!("Ready"),
+            Self::Running => pm_str!("Running"),
+            Self::Blocked => pm_str!("Blocked"),
+            Self::Sleeping => pm_str!("Sleeping"),
+            Self::Exited => pm_str!("Exited"),
+        }
+    }
+}
+impl ufmt::uDisplay for TaskState {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized,
+    {
+        ufmt::uDisplay::fmt(&self.name(), f)
+    }
+}
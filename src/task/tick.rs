@@ -0,0 +1,43 @@
+//! The kernel tick, a 1 kHz interrupt from `TC0`.
+
+use crate::task::{scheduler::TASKS, state::TaskState};
+
+use avr_device::interrupt::{self, Mutex};
+use core::cell::Cell;
+
+pub const TICK_HZ: u32 = 1000;
+const TICK_MICROS: u32 = 1_000_000 / TICK_HZ;
+/// `TC0` counts from 0 to this, inclusive, each tick: 16 MHz / 64 (prescaler) / 250 = 1 kHz.
+const TIMER_TOP: u8 = 249;
+
+static TICKS: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+/// Start ticking. Interrupts must be enabled for the tick to run.
+pub fn start(tc0: arduino_hal::pac::TC0) {
+    tc0.tccr0a.write(|w| w.wgm0().ctc());
+    tc0.ocr0a.write(|w| w.bits(TIMER_TOP));
+    tc0.tccr0b.write(|w| w.cs0().prescale_64());
+    tc0.timsk0.write(|w| w.ocie0a().set_bit());
+}
+
+/// Ticks since [`start`]. Wraps after about 50 days.
+pub fn ticks() -> u32 {
+    interrupt::free(|cs| TICKS.borrow(cs).get())
+}
+
+#[avr_device::interrupt(atmega2560)]
+fn TIMER0_COMPA() {
+    interrupt::free(|cs| {
+        let ticks = TICKS.borrow(cs);
+        ticks.set(ticks.get().wrapping_add(1));
+    });
+    // The whole tick is charged to the task it interrupted
+    if let Some(task) = unsafe { TASKS.iter_mut() }.find(|task| task.state == TaskState::Running) {
+        task.cpu_time = task.cpu_time.wrapping_add(TICK_MICROS);
+    }
+    #[cfg(feature = "monitor")]
+    {
+        crate::debug::watchpoint::check();
+        crate::debug::breakpoint::stop_for_watchpoint();
+    }
+}
@@ -1,13 +1,16 @@
-//! Array types; notably, [`PStackArrUnchecked`].
+//! Array types; notably, [`PStackArr`] and its raw layout, [`PStackArrUnchecked`].
 
 use crate::types::magic::Magic;
 
-use core::mem::MaybeUninit;
+use core::{
+    mem::MaybeUninit,
+    ops::{Deref, DerefMut, Index, IndexMut},
+};
 
 /// A stack-allocated array with a max length of `LEN`. Each element is stored as a `MaybeUninit`,
 /// and `len` creates a contract that all elements in `&self.inner[0..len]` are valid.
 ///
-/// See also [`crate::utils::decompose_uninit_array`] for self.inner`.
+/// This is the raw layout that the debugger inspects; prefer [`PStackArr`] for anything else.
 ///
 /// # Safety
 /// *Always* update `len` if `inner` is updated.
@@ -34,7 +37,7 @@ where
 {
     pub const fn new() -> Self {
         Self {
-            _magic: [0; 4],
+            _magic: Self::MAGIC,
             len: 0,
             inner: MaybeUninit::uninit_array::<LEN>(),
         }
@@ -48,3 +51,238 @@ where
         Self::new()
     }
 }
+
+/// A safe, fixed-capacity collection on top of [`PStackArrUnchecked`]. Only the first `len`
+/// elements are ever handed out or dropped.
+#[repr(transparent)]
+pub struct PStackArr<T, const LEN: usize>
+where
+    T: Sized,
+{
+    raw: PStackArrUnchecked<T, LEN>,
+}
+
+impl<T, const LEN: usize> PStackArr<T, LEN>
+where
+    T: Sized,
+{
+    pub const fn new() -> Self {
+        Self {
+            raw: PStackArrUnchecked::new(),
+        }
+    }
+
+    /// The underlying layout.
+    pub fn as_unchecked(&self) -> &PStackArrUnchecked<T, LEN> {
+        &self.raw
+    }
+    /// The underlying layout, mutably.
+    ///
+    /// # Safety
+    /// The contract of [`PStackArrUnchecked`] must still hold once the borrow ends.
+    #[require_unsafe_in_body]
+    pub unsafe fn as_unchecked_mut(&mut self) -> &mut PStackArrUnchecked<T, LEN> {
+        &mut self.raw
+    }
+
+    pub const fn capacity(&self) -> usize {
+        LEN
+    }
+    pub const fn len(&self) -> usize {
+        self.raw.len
+    }
+    pub const fn is_empty(&self) -> bool {
+        self.raw.len == 0
+    }
+    pub const fn is_full(&self) -> bool {
+        self.raw.len >= LEN
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        // SAFETY: `inner[..len]` is initialised, and `MaybeUninit<T>` has the same layout as `T`
+        unsafe { core::slice::from_raw_parts(self.raw.inner.as_ptr() as *const T, self.raw.len) }
+    }
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        // SAFETY: See `as_slice`
+        unsafe {
+            core::slice::from_raw_parts_mut(self.raw.inner.as_mut_ptr() as *mut T, self.raw.len)
+        }
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&T> {
+        self.as_slice().get(idx)
+    }
+    pub fn get_mut(&mut self, idx: usize) -> Option<&mut T> {
+        self.as_mut_slice().get_mut(idx)
+    }
+    pub fn iter(&self) -> core::slice::Iter<'_, T> {
+        self.as_slice().iter()
+    }
+    pub fn iter_mut(&mut self) -> core::slice::IterMut<'_, T> {
+        self.as_mut_slice().iter_mut()
+    }
+
+    /// Append `value` to the end. If the array is full, `value` is handed back.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        if self.is_full() {
+            return Err(value);
+        }
+        self.raw.inner[self.raw.len] = MaybeUninit::new(value);
+        self.raw.len += 1;
+        Ok(())
+    }
+    /// Remove the last element.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+        self.raw.len -= 1;
+        // SAFETY: The element was initialised and is now outside of `len`, so it won't be read again
+        Some(unsafe { self.raw.inner[self.raw.len].assume_init_read() })
+    }
+    /// Insert `value` at `idx`, shifting everything after it to the right. If the array is full or
+    /// `idx > len`, `value` is handed back.
+    pub fn insert(&mut self, idx: usize, value: T) -> Result<(), T> {
+        if self.is_full() || idx > self.raw.len {
+            return Err(value);
+        }
+        unsafe {
+            let ptr = self.raw.inner.as_mut_ptr().add(idx);
+            core::ptr::copy(ptr, ptr.add(1), self.raw.len - idx);
+            ptr.write(MaybeUninit::new(value));
+        }
+        self.raw.len += 1;
+        Ok(())
+    }
+    /// Remove the element at `idx`, shifting everything after it to the left.
+    pub fn remove(&mut self, idx: usize) -> Option<T> {
+        if idx >= self.raw.len {
+            return None;
+        }
+        self.raw.len -= 1;
+        unsafe {
+            let ptr = self.raw.inner.as_mut_ptr().add(idx);
+            let value = ptr.read().assume_init();
+            core::ptr::copy(ptr.add(1), ptr, self.raw.len - idx);
+            Some(value)
+        }
+    }
+    /// Remove the element at `idx`, replacing it with the last element. This does not preserve
+    /// ordering, but is *O(1)*.
+    pub fn swap_remove(&mut self, idx: usize) -> Option<T> {
+        if idx >= self.raw.len {
+            return None;
+        }
+        self.raw.inner.swap(idx, self.raw.len - 1);
+        self.pop()
+    }
+    /// Keep only the elements where `keep` returns `true`, preserving ordering.
+    pub fn retain<F>(&mut self, mut keep: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let len = self.raw.len;
+        // Elements are only considered initialised once they have been kept, so a panicking `keep`
+        // leaks instead of double-dropping.
+        self.raw.len = 0;
+        for idx in 0..len {
+            let ptr = self.raw.inner.as_mut_ptr();
+            if keep(unsafe { (*ptr.add(idx)).assume_init_ref() }) {
+                unsafe { core::ptr::copy(ptr.add(idx), ptr.add(self.raw.len), 1) };
+                self.raw.len += 1;
+            } else {
+                unsafe { (*ptr.add(idx)).assume_init_drop() };
+            }
+        }
+    }
+    /// Drop every element.
+    pub fn clear(&mut self) {
+        let slice = self.as_mut_slice() as *mut [T];
+        self.raw.len = 0;
+        unsafe { core::ptr::drop_in_place(slice) };
+    }
+}
+
+impl<T, const LEN: usize> Drop for PStackArr<T, LEN>
+where
+    T: Sized,
+{
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+impl<T, const LEN: usize> Default for PStackArr<T, LEN>
+where
+    T: Sized,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<T, const LEN: usize> Clone for PStackArr<T, LEN>
+where
+    T: Clone,
+{
+    fn clone(&self) -> Self {
+        let mut new = Self::new();
+        self.iter().for_each(|item| {
+            let _ = new.push(item.clone());
+        });
+        new
+    }
+}
+impl<T, const LEN: usize> Deref for PStackArr<T, LEN>
+where
+    T: Sized,
+{
+    type Target = [T];
+    fn deref(&self) -> &Self::Target {
+        self.as_slice()
+    }
+}
+impl<T, const LEN: usize> DerefMut for PStackArr<T, LEN>
+where
+    T: Sized,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.as_mut_slice()
+    }
+}
+impl<T, const LEN: usize> Index<usize> for PStackArr<T, LEN>
+where
+    T: Sized,
+{
+    type Output = T;
+    fn index(&self, idx: usize) -> &Self::Output {
+        &self.as_slice()[idx]
+    }
+}
+impl<T, const LEN: usize> IndexMut<usize> for PStackArr<T, LEN>
+where
+    T: Sized,
+{
+    fn index_mut(&mut self, idx: usize) -> &mut Self::Output {
+        &mut self.as_mut_slice()[idx]
+    }
+}
+impl<'a, T, const LEN: usize> IntoIterator for &'a PStackArr<T, LEN>
+where
+    T: Sized,
+{
+    type Item = &'a T;
+    type IntoIter = core::slice::Iter<'a, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+impl<T, const LEN: usize> ufmt::uDebug for PStackArr<T, LEN>
+where
+    T: ufmt::uDebug,
+{
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized,
+    {
+        <[T] as ufmt::uDebug>::fmt(self.as_slice(), f)
+    }
+}
@@ -1,30 +1,205 @@
 //! Error types; notably, [`PError`].
 
-use crate::types::{magic::Magic, string::PStr};
+use crate::types::{
+    array::PStackArr,
+    magic::Magic,
+    string::{pm_str, CapacityError, PStr, PmStr},
+};
 
-pub type PStaticResult<T> = Result<T, PError<0>>;
+/// Stack length of the context carried by a [`PStaticResult`].
+pub const STATIC_CONTEXT_LEN: usize = 16;
+/// How many links of a "caused by" chain are kept. Older causes are dropped. Only their variants
+/// are kept, so that every result stays small: they're returned e.g. per byte read.
+pub const MAX_CAUSES: usize = 3;
+
+pub type PStaticResult<T> = Result<T, PError<STATIC_CONTEXT_LEN>>;
 
 #[repr(C)]
 pub struct PError<const STACK_STR_LEN: usize = 64> {
     _magic: [u8; 4],
     pub variant: PErrorVariant,
     pub context: PStr<'static, STACK_STR_LEN>,
+    /// What caused this error, most recent first
+    causes: PStackArr<PErrorVariant, MAX_CAUSES>,
 }
 impl<const STACK_STR_LEN: usize> PError<STACK_STR_LEN> {
-    pub fn new(variant: PErrorVariant, context: PStr<'static, STACK_STR_LEN>) -> Self {
+    /// `context` may be a `&'static str`, a [`crate::types::string::PStackStr`], or a string in
+    /// program memory (see [`crate::types::string::pm_str`]).
+    pub fn new(variant: PErrorVariant, context: impl Into<PStr<'static, STACK_STR_LEN>>) -> Self {
         Self {
             _magic: Self::MAGIC,
             variant,
-            context,
+            context: context.into(),
+            causes: PStackArr::new(),
         }
     }
+    /// An error without any context.
+    pub fn from_variant(variant: PErrorVariant) -> Self {
+        Self::new(variant, "")
+    }
+    /// Record that `self` was caused by `cause`, along with whatever caused `cause`. Only their
+    /// variants are kept, not their contexts.
+    pub fn caused_by<const CAUSE_STR_LEN: usize>(mut self, cause: &PError<CAUSE_STR_LEN>) -> Self {
+        self.causes.clear();
+        let _ = self.causes.push(cause.variant);
+        cause.causes().take(MAX_CAUSES - 1).for_each(|&variant| {
+            let _ = self.causes.push(variant);
+        });
+        self
+    }
+    /// The "caused by" chain, most recent first.
+    pub fn causes(&self) -> core::slice::Iter<'_, PErrorVariant> {
+        self.causes.iter()
+    }
+    /// See [`PErrorVariant::code`].
+    pub fn code(&self) -> u8 {
+        self.variant.code()
+    }
 }
 unsafe impl<const STACK_STR_LEN: usize> Magic<4> for PError<STACK_STR_LEN> {
     const MAGIC: [u8; 4] = [b'e', b'r', b'r', b'!'];
 }
+impl<const STACK_STR_LEN: usize> From<PErrorVariant> for PError<STACK_STR_LEN> {
+    fn from(variant: PErrorVariant) -> Self {
+        Self::from_variant(variant)
+    }
+}
+impl<const STACK_STR_LEN: usize> From<CapacityError> for PError<STACK_STR_LEN> {
+    fn from(_: CapacityError) -> Self {
+        Self::from_variant(PErrorVariant::Overflow)
+    }
+}
+impl<const STACK_STR_LEN: usize> ufmt::uDisplay for PError<STACK_STR_LEN> {
+    /// `<variant>: <context> (caused by <variant>, caused by <variant>, ...)`
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized,
+    {
+        ufmt::uDisplay::fmt(&self.variant, f)?;
+        if !self.context.is_empty() {
+            f.write_str(": ")?;
+            ufmt::uDisplay::fmt(&self.context, f)?;
+        }
+        for (idx, cause) in self.causes().enumerate() {
+            let separator = if idx == 0 {
+                pm_str!(" (caused by ")
+            } else {
+                pm_str!(", caused by ")
+            };
+            ufmt::uDisplay::fmt(&separator, f)?;
+            ufmt::uDisplay::fmt(cause, f)?;
+        }
+        if !self.causes.is_empty() {
+            f.write_char(')')?;
+        }
+        Ok(())
+    }
+}
+impl<const STACK_STR_LEN: usize> ufmt::uDebug for PError<STACK_STR_LEN> {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized,
+    {
+        pasillo_macros::pm_uwrite!(
+            f,
+            "PError {{ variant: {:?}, code: {}, context: \"{}\", causes: [",
+            self.variant,
+            self.code(),
+            self.context
+        )?;
+        for (idx, cause) in self.causes().enumerate() {
+            if idx > 0 {
+                ufmt::uDisplay::fmt(&pm_str!(", "), f)?;
+            }
+            ufmt::uDebug::fmt(cause, f)?;
+        }
+        ufmt::uDisplay::fmt(&pm_str!("] }"), f)
+    }
+}
 
+/// The kind of a [`PError`]. The discriminant is the error's code in the syscall ABI, and thus must
+/// never change once assigned.
 #[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum PErrorVariant {
     Unknown = 0,
     Stdio = 1,
+    OutOfMemory = 2,
+    /// The operation would block, try again later.
+    WouldBlock = 3,
+    Timeout = 4,
+    InvalidDescriptor = 5,
+    Busy = 6,
+    /// A bus transaction wasn't acknowledged (NACK).
+    Bus = 7,
+    Checksum = 8,
+    Overflow = 9,
+    Permission = 10,
+    NotFound = 11,
+}
+impl PErrorVariant {
+    pub const ALL: [Self; 12] = [
+        Self::Unknown,
+        Self::Stdio,
+        Self::OutOfMemory,
+        Self::WouldBlock,
+        Self::Timeout,
+        Self::InvalidDescriptor,
+        Self::Busy,
+        Self::Bus,
+        Self::Checksum,
+        Self::Overflow,
+        Self::Permission,
+        Self::NotFound,
+    ];
+
+    /// The stable numeric code of this variant for the syscall ABI.
+    pub const fn code(self) -> u8 {
+        self as u8
+    }
+    /// The variant for a syscall ABI code. Unassigned codes map to [`Self::Unknown`].
+    pub fn from_code(code: u8) -> Self {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|variant| variant.code() == code)
+            .unwrap_or(Self::Unknown)
+    }
+    /// The name of this variant, kept in program memory.
+    pub fn name(self) -> PmStr {
+        match self {
+            Self::Unknown => pm_str!("Unknown"),
+            Self::Stdio => pm_str!("Stdio"),
+            Self::OutOfMemory => pm_str!("OutOfMemory"),
+            Self::WouldBlock => pm_str!("WouldBlock"),
+            Self::Timeout => pm_str!("Timeout"),
+            Self::InvalidDescriptor => pm_str!("InvalidDescriptor"),
+            Self::Busy => pm_str!("Busy"),
+            Self::Bus => pm_str!("Bus"),
+            Self::Checksum => pm_str!("Checksum"),
+            Self::Overflow => pm_str!("Overflow"),
+            Self::Permission => pm_str!("Permission"),
+            Self::NotFound => pm_str!("NotFound"),
+        }
+    }
+}
+impl ufmt::uDisplay for PErrorVariant {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized,
+    {
+        ufmt::uDisplay::fmt(&self.name(), f)
+    }
+}
+impl ufmt::uDebug for PErrorVariant {
+    /// `<name>(<code>)`
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized,
+    {
+        ufmt::uDisplay::fmt(&self.name(), f)?;
+        f.write_char('(')?;
+        ufmt::uDisplay::fmt(&self.code(), f)?;
+        f.write_char(')')
+    }
 }
@@ -4,66 +4,318 @@ use crate::types::magic::Magic;
 
 use core::str::FromStr;
 
-/// A padded, null-terminated string that is stored on the stack with a fixed `LEN`.
-/// The `inner` data is to be UTF-8. Derefs into `&str`.
+/// A padded string that is stored on the stack with a fixed capacity of `LEN` bytes.
+/// The `inner` data is always valid UTF-8 up until `len`. Derefs into `&str`.
 #[repr(C)]
 pub struct PStackStr<const LEN: usize> {
-    pub _magic: [u8; 4],
-    pub inner: [u8; LEN],
-    /// Not guaranteed to be updated if `inner` is modified
-    pub len: usize,
+    _magic: [u8; 4],
+    inner: [u8; LEN],
+    len: usize,
 }
 
 unsafe impl<const STR_LEN: usize> Magic<4> for PStackStr<STR_LEN> {
     const MAGIC: [u8; 4] = [u8::MAX, b's', b't', b'r'];
 }
 
+/// The string would have grown past its fixed capacity. Nothing was written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapacityError;
+impl ufmt::uDebug for CapacityError {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized,
+    {
+        f.write_str("CapacityError")
+    }
+}
+
+/// The largest index `<= idx` that lies on a char boundary of `s`.
+fn floor_char_boundary(s: &str, idx: usize) -> usize {
+    if idx >= s.len() {
+        return s.len();
+    }
+    (0..=idx).rev().find(|idx| s.is_char_boundary(*idx)).unwrap_or(0)
+}
+
+impl<const LEN: usize> PStackStr<LEN> {
+    pub const fn new() -> Self {
+        Self {
+            _magic: Self::MAGIC,
+            inner: [0; LEN],
+            len: 0,
+        }
+    }
+    /// Copies as much of `s` as fits, cutting it at a char boundary.
+    pub fn from_str_truncated(s: &str) -> Self {
+        let mut ret = Self::new();
+        ret.push_str_truncated(s);
+        ret
+    }
+
+    pub const fn capacity(&self) -> usize {
+        LEN
+    }
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Bytes left before the string is full.
+    pub const fn remaining(&self) -> usize {
+        LEN - self.len
+    }
+    pub fn as_str(&self) -> &str {
+        // SAFETY: Every method that writes `inner` only ever writes whole chars
+        unsafe { core::str::from_utf8_unchecked(&self.inner[..self.len]) }
+    }
+
+    pub fn push(&mut self, char: char) -> Result<(), CapacityError> {
+        self.push_str(char.encode_utf8(&mut [0; 4]))
+    }
+    /// Append all of `s`, or nothing at all if it doesn't fit.
+    pub fn push_str(&mut self, s: &str) -> Result<(), CapacityError> {
+        let bytes = s.as_bytes();
+        if bytes.len() > self.remaining() {
+            return Err(CapacityError);
+        }
+        self.inner[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+        Ok(())
+    }
+    /// Append as much of `s` as fits, cutting it at a char boundary. Returns whether `s` was cut.
+    pub fn push_str_truncated(&mut self, s: &str) -> bool {
+        let len = floor_char_boundary(s, self.remaining());
+        // Can't fail, as `len` fits
+        let _ = self.push_str(&s[..len]);
+        len != s.len()
+    }
+    pub fn pop(&mut self) -> Option<char> {
+        let char = self.as_str().chars().next_back()?;
+        self.len -= char.len_utf8();
+        self.inner[self.len..self.len + char.len_utf8()].fill(0);
+        Some(char)
+    }
+    /// Shorten the string to at most `new_len` bytes. If `new_len` falls in the middle of a
+    /// multi-byte char, the whole char is removed.
+    pub fn truncate(&mut self, new_len: usize) {
+        let new_len = floor_char_boundary(self.as_str(), new_len);
+        self.inner[new_len..self.len].fill(0);
+        self.len = new_len;
+    }
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+}
+
+impl<const LEN: usize> Default for PStackStr<LEN> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<const LEN: usize> Clone for PStackStr<LEN> {
+    fn clone(&self) -> Self {
+        Self {
+            _magic: Self::MAGIC,
+            inner: self.inner,
+            len: self.len,
+        }
+    }
+}
 impl<const LEN: usize> AsRef<str> for PStackStr<LEN> {
     fn as_ref(&self) -> &str {
-        unsafe { core::str::from_utf8_unchecked(&self.inner[..self.len]) }
+        self.as_str()
     }
 }
 impl<const LEN: usize> core::ops::Deref for PStackStr<LEN> {
     type Target = str;
     fn deref(&self) -> &Self::Target {
-        self.as_ref()
+        self.as_str()
+    }
+}
+impl<const LEN: usize> PartialEq<str> for PStackStr<LEN> {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
     }
 }
 impl<const LEN: usize> FromStr for PStackStr<LEN> {
-    type Err = !;
-    /// Wastefully converts from a string slice, copying the bytes up until `LEN`.
+    type Err = CapacityError;
+    /// Converts from a string slice, copying the bytes. Fails if `s` is longer than `LEN`; see
+    /// [`Self::from_str_truncated`] to cut it instead.
     /// Consider using [`PStr`] if the string won't be mutated.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut inner = [0; LEN];
+        let mut ret = Self::new();
+        ret.push_str(s)?;
+        Ok(ret)
+    }
+}
+impl<const LEN: usize> ufmt::uWrite for PStackStr<LEN> {
+    type Error = CapacityError;
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+        self.push_str(s)
+    }
+}
+impl<const LEN: usize> ufmt::uDisplay for PStackStr<LEN> {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized,
+    {
+        f.write_str(self.as_str())
+    }
+}
+impl<const LEN: usize> ufmt::uDebug for PStackStr<LEN> {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized,
+    {
+        <str as ufmt::uDebug>::fmt(self.as_str(), f)
+    }
+}
 
-        let str_bytes = s.as_bytes();
-        let len = match str_bytes.len() {
-            l if l > LEN => LEN,
-            l => l,
+/// A string slice that lives in program memory. It can't be borrowed as a `&str`; read it
+/// through [`Self::bytes`], [`Self::load`] or its [`ufmt::uDisplay`] implementation.
+#[derive(Clone, Copy)]
+pub struct PmStr {
+    ptr: *const u8,
+    len: usize,
+}
+// Program memory is never written to at runtime
+unsafe impl Sync for PmStr {}
+unsafe impl Send for PmStr {}
+impl PmStr {
+    /// # Safety
+    /// `ptr` must point to `len` bytes of valid UTF-8 in program memory.
+    pub const unsafe fn from_raw_parts(ptr: *const u8, len: usize) -> Self {
+        Self { ptr, len }
+    }
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    pub fn as_ptr(&self) -> *const u8 {
+        self.ptr
+    }
+    pub fn bytes(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..self.len).map(|idx| unsafe { avr_progmem::raw::read_byte(self.ptr.add(idx)) })
+    }
+    /// Copy the string onto the stack, cutting it at a char boundary if it is longer than `LEN`.
+    pub fn load<const LEN: usize>(&self) -> PStackStr<LEN> {
+        let mut ret = PStackStr::new();
+        self.chunks(|chunk| ret.push_str_truncated(chunk));
+        ret
+    }
+    /// Call `f` with successive pieces of the string, loaded onto the stack. Stops early if `f`
+    /// returns `true`.
+    fn chunks<F>(&self, mut f: F)
+    where
+        F: FnMut(&str) -> bool,
+    {
+        let mut buffer = [0_u8; 16];
+        let mut carry = 0;
+        let mut bytes = self.bytes();
+        loop {
+            let mut filled = carry;
+            buffer[carry..]
+                .iter_mut()
+                .zip(&mut bytes)
+                .for_each(|(slot, byte)| {
+                    *slot = byte;
+                    filled += 1;
+                });
+            if filled == 0 {
+                return;
+            }
+            // A multi-byte char may straddle the end of the buffer; carry its head over
+            let valid = match core::str::from_utf8(&buffer[..filled]) {
+                Ok(str) => str,
+                Err(error) if error.valid_up_to() > 0 => {
+                    unsafe { core::str::from_utf8_unchecked(&buffer[..error.valid_up_to()]) }
+                }
+                Err(_) => return,
+            };
+            let valid_len = valid.len();
+            if f(valid) || filled == carry {
+                return;
+            }
+            buffer.copy_within(valid_len..filled, 0);
+            carry = filled - valid_len;
+        }
+    }
+}
+/// Place a string literal in program memory, evaluating to a [`PmStr`].
+#[allow(unused_macros)]
+macro_rules! pm_str {
+    ($str:literal) => {{
+        const BYTES: &[u8] = $str.as_bytes();
+        #[link_section = ".progmem.data"]
+        static PM_STR: [u8; BYTES.len()] = {
+            let mut array = [0; BYTES.len()];
+            let mut idx = 0;
+            while idx < BYTES.len() {
+                array[idx] = BYTES[idx];
+                idx += 1;
+            }
+            array
         };
-        inner[0..len].clone_from_slice(&str_bytes[0..len]);
+        // SAFETY: `PM_STR` is a copy of a `str` literal, placed in program memory
+        unsafe { crate::types::string::PmStr::from_raw_parts(PM_STR.as_ptr(), BYTES.len()) }
+    }};
+}
+#[allow(unused_imports)]
+pub(crate) use pm_str;
 
-        Ok(Self {
-            _magic: Self::MAGIC,
-            len,
-            inner,
-        })
+impl<const N: usize> From<&'static avr_progmem::string::PmString<N>> for PmStr {
+    fn from(value: &'static avr_progmem::string::PmString<N>) -> Self {
+        // SAFETY: `PmString` guarantees `N` bytes of UTF-8 in program memory
+        unsafe { Self::from_raw_parts(value.as_bytes().as_ptr() as *const u8, N) }
+    }
+}
+impl ufmt::uDisplay for PmStr {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized,
+    {
+        let mut result = Ok(());
+        self.chunks(|chunk| {
+            result = f.write_str(chunk);
+            result.is_err()
+        });
+        result
     }
 }
 
-/// A string that could be a static str slice or custom string. The string type is determined through
-/// a magic value; if none is present, the string is assumed to be a str slice.
+/// A string that could be a str slice, a custom stack string, or a string in program memory. The
+/// variant is an explicit tag byte, followed by the largest of the three.
 ///
-/// The size of this union is always the stack length.
-pub union PStr<'a, const STACK_LEN: usize = 0> {
-    fixed: &'a str,
-    stack: core::mem::ManuallyDrop<PStackStr<STACK_LEN>>,
+/// The size of this enum is thus always the stack length, plus the tag and [`PStackStr`] overhead.
+#[repr(u8)]
+pub enum PStr<'a, const STACK_LEN: usize = 0> {
+    Fixed(&'a str) = 0,
+    Stack(PStackStr<STACK_LEN>) = 1,
+    Progmem(PmStr) = 2,
 }
 impl<'a, const STACK_LEN: usize> From<PStackStr<STACK_LEN>> for PStr<'a, STACK_LEN> {
     fn from(value: PStackStr<STACK_LEN>) -> Self {
-        Self {
-            stack: core::mem::ManuallyDrop::new(value),
-        }
+        Self::Stack(value)
+    }
+}
+impl<'a, const STACK_LEN: usize> From<&'a str> for PStr<'a, STACK_LEN> {
+    fn from(value: &'a str) -> Self {
+        Self::Fixed(value)
+    }
+}
+impl<'a, const STACK_LEN: usize> From<PmStr> for PStr<'a, STACK_LEN> {
+    fn from(value: PmStr) -> Self {
+        Self::Progmem(value)
+    }
+}
+impl<'a, const STACK_LEN: usize, const N: usize> From<&'static avr_progmem::string::PmString<N>>
+    for PStr<'a, STACK_LEN>
+{
+    fn from(value: &'static avr_progmem::string::PmString<N>) -> Self {
+        Self::Progmem(value.into())
     }
 }
 impl<'a, const STACK_LEN: usize> PStr<'a, STACK_LEN> {
@@ -71,17 +323,36 @@ impl<'a, const STACK_LEN: usize> PStr<'a, STACK_LEN> {
     // And `str` has the `?Sized` supertrait, so `From<str>` wouldn't work.
     #[allow(clippy::should_implement_trait)]
     pub fn from_str(str: &'a str) -> Self {
-        Self { fixed: str }
+        Self::Fixed(str)
+    }
+    /// The string as a slice, if it lives in data memory.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::Fixed(str) => Some(str),
+            Self::Stack(stack) => Some(stack.as_str()),
+            Self::Progmem(_) => None,
+        }
+    }
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Fixed(str) => str.len(),
+            Self::Stack(stack) => stack.len(),
+            Self::Progmem(progmem) => progmem.len(),
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 }
-impl<'a, const STACK_LEN: usize> AsRef<str> for PStr<'a, STACK_LEN> {
-    fn as_ref(&self) -> &str {
-        unsafe {
-            if Magic::is_magic(core::ptr::addr_of!(self.stack) as *const PStackStr<STACK_LEN>) {
-                self.stack.as_ref()
-            } else {
-                self.fixed
-            }
+impl<'a, const STACK_LEN: usize> ufmt::uDisplay for PStr<'a, STACK_LEN> {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized,
+    {
+        match self {
+            Self::Fixed(str) => f.write_str(str),
+            Self::Stack(stack) => f.write_str(stack.as_str()),
+            Self::Progmem(progmem) => ufmt::uDisplay::fmt(progmem, f),
         }
     }
 }
@@ -1,7 +1,5 @@
 //! Generic helper utilities.
 
-use core::mem::MaybeUninit;
-
 /// [`core::mem::transmute`] ensures that `sizeof::<Src>()` == `sizeof::<Dst>()`. In the context of
 /// const generics (ref. https://github.com/rust-lang/rust/issues/47966), this cannot be guaranteed.
 ///
@@ -18,11 +16,73 @@ where
     destination
 }
 
-/// Unsafely transmutes a [`MaybeUninit::uninit_array`], masking the [`MaybeUninit`]s.
-#[require_unsafe_in_body]
-#[inline(never)]
-pub unsafe fn decompose_uninit_array<T, const LEN: usize>(
-    array: [MaybeUninit<T>; LEN],
-) -> [T; LEN] {
-    unsafe { unchecked_size_transmute::<_, [T; LEN]>(array) }
+/// Write `value` in lowercase hexadecimal to `f`, zero padded to at least `digits` digits.
+pub fn write_hex<W>(
+    f: &mut ufmt::Formatter<'_, W>,
+    value: u32,
+    digits: usize,
+) -> Result<(), W::Error>
+where
+    W: ufmt::uWrite + ?Sized,
+{
+    let significant = (8 - value.leading_zeros() as usize / 4).max(1);
+    (0..significant.max(digits)).rev().try_for_each(|nibble| {
+        let digit = value.checked_shr(nibble as u32 * 4).unwrap_or(0) & 0xf;
+        f.write_char(char::from_digit(digit, 16).unwrap_or('?'))
+    })
+}
+
+/// CRC-16/CCITT-FALSE: polynomial 0x1021, initially 0xffff.
+pub fn crc16(bytes: &[u8]) -> u16 {
+    bytes
+        .iter()
+        .fold(CRC16_INIT, |crc, &byte| crc16_update(crc, byte))
+}
+/// [`crc16`]'s initial value, for [`crc16_update`].
+pub const CRC16_INIT: u16 = 0xffff;
+/// Add `byte` to `crc`, for data that isn't in one slice, e.g. in program memory.
+pub fn crc16_update(crc: u16, byte: u8) -> u16 {
+    (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+        if crc & 0x8000 != 0 {
+            (crc << 1) ^ 0x1021
+        } else {
+            crc << 1
+        }
+    })
+}
+
+/// Writes text a byte at a time through `write`, up to `limit` bytes. Past that, writing fails so
+/// that formatting stops early, rather than running on through e.g. a long `Debug` impl.
+pub struct BoundedWriter<F: FnMut(u8)> {
+    write: F,
+    remaining: usize,
+}
+impl<F: FnMut(u8)> BoundedWriter<F> {
+    pub fn new(limit: usize, write: F) -> Self {
+        Self {
+            write,
+            remaining: limit,
+        }
+    }
+    fn write_bytes(&mut self, text: &str) -> Result<(), ()> {
+        for byte in text.bytes() {
+            if self.remaining == 0 {
+                return Err(());
+            }
+            (self.write)(byte);
+            self.remaining -= 1;
+        }
+        Ok(())
+    }
+}
+impl<F: FnMut(u8)> core::fmt::Write for BoundedWriter<F> {
+    fn write_str(&mut self, text: &str) -> core::fmt::Result {
+        self.write_bytes(text).map_err(|_| core::fmt::Error)
+    }
+}
+impl<F: FnMut(u8)> ufmt::uWrite for BoundedWriter<F> {
+    type Error = ();
+    fn write_str(&mut self, text: &str) -> Result<(), ()> {
+        self.write_bytes(text)
+    }
 }